@@ -1,8 +1,13 @@
+use std::num::{NonZeroU64, NonZeroUsize};
 use std::path::PathBuf;
 
-use clap::StructOpt;
+use clap::{ArgGroup, StructOpt};
 
-use crate::split::{ProportionSplit, RowSplit};
+use crate::error::{Error, Result};
+use crate::io::{
+    Column, Compression, CsvDialect, RecordFormat, RecordSeparator, MAX_COMPRESSION_LEVEL,
+};
+use crate::split::{OutputTemplate, ProportionSplit, RowSplit, RunDir};
 
 #[derive(Debug, StructOpt)]
 #[clap(
@@ -22,15 +27,56 @@ pub struct Opt {
 }
 
 #[derive(Debug, StructOpt)]
+#[allow(clippy::large_enum_variant)]
 pub enum Command {
     #[clap(
         name = "split",
         about = "Split dataset into two or more files for test/train/validation sets"
     )]
     Split(Split),
+    #[clap(
+        name = "verify",
+        about = "Check that the outputs of a split match their manifest"
+    )]
+    Verify(Verify),
+    #[clap(name = "count", about = "Count the records in a dataset")]
+    Count(Count),
+    #[clap(
+        name = "sample",
+        about = "Print a uniform random sample of a dataset, with its header"
+    )]
+    Sample(Sample),
+    #[clap(
+        name = "shuffle",
+        about = "Shuffle every row of a dataset, using a bounded amount of memory"
+    )]
+    Shuffle(Shuffle),
+    #[clap(
+        name = "stats",
+        about = "Compare statistics of the splits written by a previous run"
+    )]
+    Stats(Stats),
+    #[clap(
+        name = "check-leakage",
+        about = "Check that no row or key appears in more than one split of a previous run"
+    )]
+    CheckLeakage(CheckLeakage),
+    #[clap(
+        name = "check-contamination",
+        about = "Check the splits of a previous run for rows matching a reference dataset, such as a public test set"
+    )]
+    CheckContamination(CheckContamination),
+    #[clap(
+        name = "merge",
+        about = "Concatenate chunks or splits into one file, writing the header once"
+    )]
+    Merge(Merge),
+    #[clap(name = "run", about = "Run the split jobs described in a job file")]
+    Run(Run),
 }
 
 #[derive(Debug, StructOpt)]
+#[clap(group(ArgGroup::new("compression").multiple(true).args(&["compress-output", "bgzf"])))]
 pub struct Split {
     #[clap(
         short = 'r',
@@ -52,6 +98,9 @@ pub struct Split {
     )]
     pub prop: Vec<ProportionSplit>,
 
+    #[clap(flatten)]
+    pub records: RecordOptions,
+
     #[clap(
         short = 'n',
         long = "no-header",
@@ -64,7 +113,34 @@ pub struct Split {
         long = "chunk-size",
         help = "Maximum number of rows per output chunk"
     )]
-    pub chunk_size: Option<u64>,
+    pub chunk_size: Option<NonZeroU64>,
+
+    #[clap(
+        long = "chunk-bytes",
+        parse(try_from_str = parse_size),
+        help = "Maximum size of each output chunk in bytes, e.g. 256M. Units K, M, G and T are powers of 1024"
+    )]
+    pub chunk_bytes: Option<u64>,
+
+    #[clap(
+        long = "chunk-bytes-compressed",
+        requires = "chunk-bytes",
+        help = "Apply --chunk-bytes to the compressed size of each chunk, rather than the uncompressed size. The compressed size is only known a block at a time, so chunks may overshoot the limit by a few compressed blocks"
+    )]
+    pub chunk_bytes_compressed: bool,
+
+    #[clap(
+        long = "shards",
+        conflicts_with_all = &["chunk-size", "chunk-bytes"],
+        help = "Write exactly this many chunks per split, with row counts differing by at most one. With --contiguous and proportion splits, requires --total-rows and is only as even as that estimate"
+    )]
+    pub shards: Option<NonZeroU64>,
+
+    #[clap(
+        long = "contiguous",
+        help = "Write a consecutive run of each split's rows to each chunk, preserving the input order across chunks, rather than distributing rows between chunks in turn"
+    )]
+    pub contiguous: bool,
 
     #[clap(
         short = 't',
@@ -76,6 +152,212 @@ pub struct Split {
     #[clap(short = 's', long = "seed", help = "RNG seed, for reproducibility")]
     pub seed: Option<u64>,
 
+    #[clap(
+        parse(from_os_str),
+        required = true,
+        help = "Data to split, optionally gzip compressed. Multiple files, directories and glob patterns are read in order as one dataset. If '-', read from stdin"
+    )]
+    pub input: Vec<PathBuf>,
+
+    #[clap(
+        short = 'o',
+        long = "output-prefix",
+        parse(from_os_str),
+        help = "Output filename prefix. Required if reading from stdin or multiple input files, unless --output-template doesn't use {stem}"
+    )]
+    pub output_prefix: Option<PathBuf>,
+
+    #[clap(
+        long = "output-dir",
+        parse(from_os_str),
+        help = "Directory to write outputs to. Defaults to the directory of the input or output prefix"
+    )]
+    pub output_dir: Option<PathBuf>,
+
+    #[clap(
+        long = "output-template",
+        help = "Template for output paths relative to the output directory, e.g. '{split}/{stem}-{chunk:04}.{ext}'. Placeholders are {split}, {stem}, {chunk}, {chunks}, {ext} and {seed}, optionally with a fill character and width such as {chunk:_6}"
+    )]
+    pub output_template: Option<OutputTemplate>,
+
+    #[clap(
+        long = "output-extension",
+        help = "Extension of output files, before any compression suffix. Defaults to the extension of the input or output prefix, or 'csv'"
+    )]
+    pub output_extension: Option<String>,
+
+    #[clap(
+        short = 'C',
+        long = "compressed-output",
+        help = "Compress output files using gzip"
+    )]
+    pub compress_output: bool,
+
+    #[clap(
+        long = "bgzf",
+        help = "Compress output files as BGZF, blocked gzip which can still be read by gunzip, and write a row index next to each one for random access to rows. Implies --compressed-output"
+    )]
+    pub bgzf: bool,
+
+    #[clap(
+        long = "compression-level",
+        requires = "compression",
+        parse(try_from_str = parse_compression_level),
+        help = "Gzip compression level for output files, from 0 (none) to 9 (smallest) [default: 6]"
+    )]
+    pub compression_level: Option<u32>,
+
+    #[clap(
+        long = "compression-threads",
+        requires = "compression",
+        help = "Number of threads used to compress output files. Defaults to the number of CPUs. The output is the same whatever the number of threads"
+    )]
+    pub compression_threads: Option<NonZeroUsize>,
+
+    #[clap(
+        long = "shuffle",
+        conflicts_with = "chunk-bytes-compressed",
+        help = "Shuffle the rows of each output file, rather than keeping them in input order"
+    )]
+    pub shuffle: bool,
+
+    #[clap(
+        long = "shuffle-memory",
+        requires = "shuffle",
+        parse(try_from_str = parse_size),
+        help = "Memory to use for shuffling, shared by the output files being written, e.g. 1G. Rows beyond this are shuffled on disk [default: 512M]"
+    )]
+    pub shuffle_memory: Option<u64>,
+
+    #[clap(
+        long = "temp-dir",
+        requires = "shuffle",
+        parse(from_os_str),
+        help = "Directory for temporary files when shuffling on disk. Defaults to the system temporary directory"
+    )]
+    pub temp_dir: Option<PathBuf>,
+
+    #[clap(
+        long = "fsync",
+        help = "Sync each output to disk before moving it into place, so that complete outputs survive a crash"
+    )]
+    pub fsync: bool,
+
+    #[clap(long = "force", help = "Overwrite the outputs of a previous run")]
+    pub force: bool,
+
+    #[clap(
+        long = "run-dir",
+        possible_values = &["timestamp", "seed"],
+        help = "Write outputs to a fresh directory inside the output directory, named after the current time or the seed"
+    )]
+    pub run_dir: Option<RunDir>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Count {
+    #[clap(flatten)]
+    pub records: RecordOptions,
+
+    #[clap(
+        short = 'n',
+        long = "no-header",
+        help = "Don't treat the first row as a header"
+    )]
+    pub no_header: bool,
+
+    #[clap(
+        long = "cache",
+        help = "Cache the count of each input in a hidden file next to it, and reuse it while the input is unchanged"
+    )]
+    pub cache: bool,
+
+    #[clap(
+        parse(from_os_str),
+        required = true,
+        help = "Data to count, optionally gzip compressed. Multiple files, directories and glob patterns are counted as one dataset. If '-', read from stdin"
+    )]
+    pub input: Vec<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Sample {
+    #[clap(
+        short = 'n',
+        long = "rows",
+        required_unless_present = "prop",
+        conflicts_with = "prop",
+        help = "Sample exactly this many rows, or every row if there are fewer"
+    )]
+    pub rows: Option<u64>,
+
+    #[clap(
+        short = 'p',
+        long = "prop",
+        required_unless_present = "rows",
+        conflicts_with = "rows",
+        help = "Sample each row with this probability"
+    )]
+    pub prop: Option<f64>,
+
+    #[clap(short = 's', long = "seed", help = "RNG seed, for reproducibility")]
+    pub seed: Option<u64>,
+
+    #[clap(flatten)]
+    pub records: RecordOptions,
+
+    #[clap(long = "no-header", help = "Don't treat the first row as a header")]
+    pub no_header: bool,
+
+    #[clap(
+        parse(from_os_str),
+        required = true,
+        help = "Data to sample, optionally gzip compressed. Multiple files, directories and glob patterns are read in order as one dataset. If '-', read from stdin"
+    )]
+    pub input: Vec<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Shuffle {
+    #[clap(short = 's', long = "seed", help = "RNG seed, for reproducibility")]
+    pub seed: Option<u64>,
+
+    #[clap(
+        long = "memory",
+        parse(try_from_str = parse_size),
+        help = "Memory to hold rows in, e.g. 4G. Larger inputs are shuffled on disk [default: 512M]"
+    )]
+    pub memory: Option<u64>,
+
+    #[clap(
+        long = "temp-dir",
+        parse(from_os_str),
+        help = "Directory for temporary files when shuffling on disk. Defaults to the system temporary directory"
+    )]
+    pub temp_dir: Option<PathBuf>,
+
+    #[clap(flatten)]
+    pub records: RecordOptions,
+
+    #[clap(
+        short = 'n',
+        long = "no-header",
+        help = "Don't treat the first row as a header"
+    )]
+    pub no_header: bool,
+
+    #[clap(
+        parse(from_os_str),
+        required = true,
+        help = "Data to shuffle, optionally gzip compressed. Multiple files, directories and glob patterns are read in order as one dataset. If '-', read from stdin"
+    )]
+    pub input: Vec<PathBuf>,
+}
+
+/// Options for how input is read and divided into records, shared by every
+/// subcommand which reads a dataset.
+#[derive(Debug, clap::Args)]
+pub struct RecordOptions {
     #[clap(
         long = "csv",
         help = "Parse input as CSV. Only needed if rows contain embedded newlines - will impact performance."
@@ -83,19 +365,92 @@ pub struct Split {
     pub csv: bool,
 
     #[clap(
-        parse(from_os_str),
-        help = "Data to split, optionally gzip compressed. If '-', read from stdin"
+        long = "fasta",
+        conflicts_with_all = &[
+            "csv", "fastq", "lines-per-record",
+            "delimiter", "quote", "escape", "no-double-quote", "comment", "terminator", "sniff",
+        ],
+        help = "Parse input as FASTA, keeping each multi-line record together. FASTA input has no header row"
     )]
-    pub input: PathBuf,
+    pub fasta: bool,
 
     #[clap(
-        short = 'o',
-        long = "output-prefix",
-        parse(from_os_str),
-        required_if_eq("input", "-"),
-        help = "Output filename prefix. Only used if reading from stdin"
+        long = "fastq",
+        conflicts_with_all = &[
+            "csv", "fasta", "lines-per-record",
+            "delimiter", "quote", "escape", "no-double-quote", "comment", "terminator", "sniff",
+        ],
+        help = "Parse input as FASTQ, keeping each four line record together. FASTQ input has no header row"
     )]
-    pub output_prefix: Option<PathBuf>,
+    pub fastq: bool,
+
+    #[clap(
+        long = "lines-per-record",
+        conflicts_with_all = &[
+            "csv", "fasta", "fastq",
+            "delimiter", "quote", "escape", "no-double-quote", "comment", "terminator", "sniff",
+        ],
+        help = "Treat each group of this many lines as a single record"
+    )]
+    pub lines_per_record: Option<NonZeroUsize>,
+
+    #[clap(
+        long = "record-separator",
+        conflicts_with_all = &[
+            "csv", "fasta", "fastq", "lines-per-record",
+            "delimiter", "quote", "escape", "no-double-quote", "comment", "terminator", "sniff",
+        ],
+        help = "Separate plain text records with this string rather than newlines. Use 'blank' for blank lines, 'regex:<pattern>' for a regular expression, or a literal string which may contain escapes such as '\\0'"
+    )]
+    pub record_separator: Option<RecordSeparator>,
+
+    #[clap(
+        long = "delimiter",
+        parse(try_from_str = parse_byte),
+        help = "CSV field delimiter, such as ';' or '\\t'. Implies --csv"
+    )]
+    pub delimiter: Option<u8>,
+
+    #[clap(
+        long = "quote",
+        parse(try_from_str = parse_byte),
+        help = "CSV quote character. Implies --csv"
+    )]
+    pub quote: Option<u8>,
+
+    #[clap(
+        long = "escape",
+        parse(try_from_str = parse_byte),
+        help = "CSV escape character for quotes inside quoted fields. Implies --csv"
+    )]
+    pub escape: Option<u8>,
+
+    #[clap(
+        long = "no-double-quote",
+        help = "Don't treat doubled quotes inside quoted fields as escaped quotes. Implies --csv"
+    )]
+    pub no_double_quote: bool,
+
+    #[clap(
+        long = "comment",
+        parse(try_from_str = parse_byte),
+        help = "Skip CSV lines starting with this character. Implies --csv"
+    )]
+    pub comment: Option<u8>,
+
+    #[clap(
+        long = "terminator",
+        parse(try_from_str = parse_byte),
+        help = "CSV record terminator. Defaults to any of '\\r', '\\n' or '\\r\\n'. Implies --csv"
+    )]
+    pub terminator: Option<u8>,
+
+    #[clap(
+        long = "sniff",
+        conflicts_with = "delimiter",
+        help = "Detect the CSV delimiter from the start of the input. Implies --csv"
+    )]
+    pub sniff: bool,
 
     #[clap(
         short = 'd',
@@ -103,11 +458,321 @@ pub struct Split {
         help = "Decompress input from gzip format"
     )]
     pub decompress_input: bool,
+}
+
+impl RecordOptions {
+    /// The record format chosen by these options, one line per record by
+    /// default.
+    pub fn record_format(&self) -> RecordFormat {
+        if self.fasta {
+            return RecordFormat::Fasta;
+        }
+        if self.fastq {
+            return RecordFormat::Fastq;
+        }
+        if let Some(lines) = self.lines_per_record {
+            return RecordFormat::MultiLine(lines);
+        }
+        if let Some(separator) = &self.record_separator {
+            return RecordFormat::Separated(separator.clone());
+        }
+        let dialect_options = [
+            self.delimiter,
+            self.quote,
+            self.escape,
+            self.comment,
+            self.terminator,
+        ];
+        if self.csv
+            || self.no_double_quote
+            || self.sniff
+            || dialect_options.iter().any(Option::is_some)
+        {
+            let default = CsvDialect::default();
+            return RecordFormat::Csv(CsvDialect {
+                delimiter: self.delimiter.unwrap_or(default.delimiter),
+                quote: self.quote.unwrap_or(default.quote),
+                escape: self.escape,
+                double_quote: !self.no_double_quote,
+                comment: self.comment,
+                terminator: self.terminator,
+                sniff: self.sniff,
+            });
+        }
+        RecordFormat::Lines
+    }
+
+    pub fn input_compression(&self) -> Compression {
+        if self.decompress_input {
+            Compression::GzipCompression
+        } else {
+            Compression::Uncompressed
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Merge {
+    #[clap(
+        short = 'o',
+        long = "output",
+        parse(from_os_str),
+        help = "File to write to. If not given, write to stdout"
+    )]
+    pub output: Option<PathBuf>,
 
     #[clap(
         short = 'C',
         long = "compressed-output",
-        help = "Compress output files using gzip"
+        help = "Compress the output using gzip"
     )]
     pub compress_output: bool,
+
+    #[clap(
+        long = "check-header",
+        help = "Check that every input has the same header as the first"
+    )]
+    pub check_header: bool,
+
+    #[clap(
+        long = "force",
+        requires = "output",
+        help = "Overwrite an existing output"
+    )]
+    pub force: bool,
+
+    #[clap(flatten)]
+    pub records: RecordOptions,
+
+    #[clap(
+        short = 'n',
+        long = "no-header",
+        help = "Don't treat the first row of each input as a header"
+    )]
+    pub no_header: bool,
+
+    #[clap(
+        parse(from_os_str),
+        required = true,
+        help = "Files to merge, in order. Gzip compressed files are detected and decompressed, unless reading from '-' which needs -d. Files in a directory or matching a glob pattern are merged in chunk order, comparing numbers in their names numerically"
+    )]
+    pub input: Vec<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+#[clap(trailing_var_arg = true, allow_hyphen_values = true)]
+pub struct Run {
+    #[clap(
+        long = "job",
+        help = "Only run these jobs, given by name. Jobs without a name are named after their position in the file, counting from 1",
+        use_value_delimiter = true
+    )]
+    pub job: Vec<String>,
+
+    #[clap(
+        parse(from_os_str),
+        help = "TOML file describing the jobs. Its keys are the options of the split command, e.g. 'chunk-size = 1000'"
+    )]
+    pub file: PathBuf,
+
+    #[clap(
+        multiple_values = true,
+        allow_hyphen_values = true,
+        help = "Options of the split command, e.g. '--seed 42 --force', overriding the values in the file for every job"
+    )]
+    pub overrides: Vec<String>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Verify {
+    #[clap(
+        default_value = ".",
+        help = "The manifest written by a split, e.g. data.manifest.json, or the directory containing it"
+    )]
+    pub manifest: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Stats {
+    #[clap(
+        short = 'l',
+        long = "label",
+        help = "Compare the distribution of this column, given by name or by position counting from 1"
+    )]
+    pub label: Option<Column>,
+
+    #[clap(
+        long = "max-difference",
+        help = "Flag proportions which differ between splits by more than this, and means which differ by more than this many standard deviations [default: 0.1]"
+    )]
+    pub max_difference: Option<f64>,
+
+    #[clap(
+        long = "json",
+        help = "Print the statistics as JSON rather than a table"
+    )]
+    pub json: bool,
+
+    #[clap(
+        default_value = ".",
+        help = "The manifest written by a split, e.g. data.manifest.json, or the directory containing only it"
+    )]
+    pub manifest: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct CheckLeakage {
+    #[clap(
+        short = 'k',
+        long = "key",
+        help = "Compare these columns, given by name or by position counting from 1, rather than whole rows. Rows with an empty key are skipped",
+        use_value_delimiter = true
+    )]
+    pub key: Vec<Column>,
+
+    #[clap(
+        long = "memory",
+        parse(try_from_str = parse_size),
+        help = "Memory to hold key hashes in, e.g. 4G. Larger outputs are checked in partitions on disk [default: 512M]"
+    )]
+    pub memory: Option<u64>,
+
+    #[clap(
+        long = "temp-dir",
+        parse(from_os_str),
+        help = "Directory for temporary files when checking on disk. Defaults to the system temporary directory"
+    )]
+    pub temp_dir: Option<PathBuf>,
+
+    #[clap(
+        long = "examples",
+        help = "Number of leaked keys to print [default: 5]"
+    )]
+    pub examples: Option<usize>,
+
+    #[clap(
+        default_value = ".",
+        help = "The manifest written by a split, e.g. data.manifest.json, or the directory containing only it"
+    )]
+    pub manifest: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct CheckContamination {
+    #[clap(
+        short = 'r',
+        long = "reference",
+        parse(from_os_str),
+        required = true,
+        help = "Reference data to check against, optionally gzip compressed. May be given more than once, and may be a file, directory or glob pattern"
+    )]
+    pub reference: Vec<PathBuf>,
+
+    #[clap(
+        short = 'k',
+        long = "key",
+        help = "Match rows whose values of these columns are found in the reference, given by name or by position counting from 1. Without --key or --text, whole rows are matched",
+        use_value_delimiter = true
+    )]
+    pub key: Vec<Column>,
+
+    #[clap(
+        long = "text",
+        help = "Match rows whose text in this column shares enough word n-grams with the reference's text in the same column"
+    )]
+    pub text: Option<Column>,
+
+    #[clap(
+        long = "ngram",
+        requires = "text",
+        help = "Number of words in each n-gram compared by --text [default: 8]"
+    )]
+    pub ngram: Option<NonZeroUsize>,
+
+    #[clap(
+        long = "threshold",
+        requires = "text",
+        help = "Proportion of a row's n-grams which must be found in the reference for it to match [default: 0.5]"
+    )]
+    pub threshold: Option<f64>,
+
+    #[clap(
+        long = "splits",
+        help = "Only check these splits, e.g. train,validation. Defaults to every split",
+        use_value_delimiter = true
+    )]
+    pub splits: Vec<String>,
+
+    #[clap(
+        long = "remove",
+        help = "Remove matching rows from the outputs, updating the manifest and checksums"
+    )]
+    pub remove: bool,
+
+    #[clap(
+        long = "examples",
+        help = "Number of matching rows to print for each split [default: 5]"
+    )]
+    pub examples: Option<usize>,
+
+    #[clap(flatten)]
+    pub records: RecordOptions,
+
+    #[clap(
+        short = 'n',
+        long = "no-header",
+        help = "The reference has no header row. Columns must then be given by position"
+    )]
+    pub no_header: bool,
+
+    #[clap(
+        default_value = ".",
+        help = "The manifest written by a split, e.g. data.manifest.json, or the directory containing only it"
+    )]
+    pub manifest: PathBuf,
+}
+
+/// Parse a single byte, such as a CSV delimiter, from a command line argument.
+///
+/// Accepts a single ASCII character or one of the escapes `\t`, `\n`, `\r`
+/// and `\0`.
+pub(crate) fn parse_byte(s: &str) -> Result<u8> {
+    match s {
+        "\\t" | "tab" => Ok(b'\t'),
+        "\\n" => Ok(b'\n'),
+        "\\r" => Ok(b'\r'),
+        "\\0" => Ok(b'\0'),
+        _ if s.len() == 1 && s.is_ascii() => Ok(s.as_bytes()[0]),
+        _ => Err(Error::InvalidCharacter(s.to_string())),
+    }
+}
+
+/// Parse a gzip compression level from a command line argument.
+pub(crate) fn parse_compression_level(s: &str) -> Result<u32> {
+    let level = s.trim().parse()?;
+    if level > MAX_COMPRESSION_LEVEL {
+        return Err(Error::InvalidCompressionLevel(level));
+    }
+    Ok(level)
+}
+
+/// Parse a size in bytes, such as `1024`, `64K` or `256MB`, from a command line
+/// argument. Units are powers of 1024.
+pub(crate) fn parse_size(s: &str) -> Result<u64> {
+    let upper = s.trim().to_ascii_uppercase();
+    let number = upper.trim_end_matches("IB").trim_end_matches('B');
+    let (number, multiplier) = match number.chars().last() {
+        Some('K') => (&number[..number.len() - 1], 1 << 10),
+        Some('M') => (&number[..number.len() - 1], 1 << 20),
+        Some('G') => (&number[..number.len() - 1], 1 << 30),
+        Some('T') => (&number[..number.len() - 1], 1 << 40),
+        _ => (number, 1),
+    };
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .filter(|n| *n > 0)
+        .ok_or_else(|| Error::InvalidSize(s.to_string()))
 }
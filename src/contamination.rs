@@ -0,0 +1,622 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::remove_file;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+
+use log::info;
+
+use crate::error::{Error, Result};
+use crate::io::{
+    expand_inputs, index_path, open_data, open_output, Column, Compression, FieldReader,
+    GzipCompressor, GzipFormat, RecordFormat, RowIndexWriter, SharedChecksum,
+};
+use crate::leakage::hash_key;
+use crate::split::{ChunkSummary, Manifest, OutputFiles};
+
+/// The default number of words in each n-gram compared between text columns.
+pub const DEFAULT_NGRAM: usize = 8;
+
+/// The default proportion of a text's n-grams which must be found in the
+/// reference for it to match.
+pub const DEFAULT_OVERLAP_THRESHOLD: f64 = 0.5;
+
+/// The default number of matching rows to print for each split.
+pub const DEFAULT_CONTAMINATION_EXAMPLES: usize = 5;
+
+/// The longest row printed as an example.
+const PREVIEW_CHARS: usize = 80;
+
+pub struct ContaminationCheckerBuilder {
+    /// A manifest, or a directory containing one
+    path: PathBuf,
+    /// The paths, directories or glob patterns to read reference rows from
+    references: Vec<PathBuf>,
+    /// The columns to match exactly, or empty to match whole rows
+    keys: Vec<Column>,
+    /// A text column to compare by n-gram overlap
+    text: Option<Column>,
+    /// The number of words in each n-gram
+    ngram: usize,
+    /// The proportion of n-grams which must overlap for a match
+    threshold: f64,
+    /// The splits to check, or empty for every split
+    splits: Vec<String>,
+    /// Should matching rows be removed from the outputs?
+    remove: bool,
+    /// The number of matching rows to print for each split
+    examples: usize,
+    /// Compression for reference files
+    reference_compression: Compression,
+    /// How the reference is divided into records
+    reference_format: RecordFormat,
+    /// Do the reference files have headers?
+    ///
+    /// Note: defaults to true.
+    reference_has_header: bool,
+}
+
+impl ContaminationCheckerBuilder {
+    pub fn new<P: AsRef<Path>, Q: AsRef<Path>>(path: P, references: &[Q]) -> Self {
+        ContaminationCheckerBuilder {
+            path: path.as_ref().to_path_buf(),
+            references: references
+                .iter()
+                .map(|r| r.as_ref().to_path_buf())
+                .collect(),
+            keys: Vec::new(),
+            text: None,
+            ngram: DEFAULT_NGRAM,
+            threshold: DEFAULT_OVERLAP_THRESHOLD,
+            splits: Vec::new(),
+            remove: false,
+            examples: DEFAULT_CONTAMINATION_EXAMPLES,
+            reference_compression: Compression::Uncompressed,
+            reference_format: RecordFormat::Lines,
+            reference_has_header: true,
+        }
+    }
+
+    #[must_use]
+    pub fn keys(mut self, keys: Vec<Column>) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    #[must_use]
+    pub fn text(mut self, text: Column) -> Self {
+        self.text = Some(text);
+        self
+    }
+
+    #[must_use]
+    pub fn ngram(mut self, ngram: NonZeroUsize) -> Self {
+        self.ngram = ngram.get();
+        self
+    }
+
+    #[must_use]
+    pub fn threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    #[must_use]
+    pub fn splits(mut self, splits: Vec<String>) -> Self {
+        self.splits = splits;
+        self
+    }
+
+    #[must_use]
+    pub fn remove(mut self, remove: bool) -> Self {
+        self.remove = remove;
+        self
+    }
+
+    #[must_use]
+    pub fn examples(mut self, examples: usize) -> Self {
+        self.examples = examples;
+        self
+    }
+
+    #[must_use]
+    pub fn reference_compression(mut self, reference_compression: Compression) -> Self {
+        self.reference_compression = reference_compression;
+        self
+    }
+
+    #[must_use]
+    pub fn reference_format(mut self, format: RecordFormat) -> Self {
+        self.reference_format = format;
+        self
+    }
+
+    #[must_use]
+    pub fn reference_has_header(mut self, has_header: bool) -> Self {
+        self.reference_has_header = has_header;
+        self
+    }
+
+    pub fn build(self) -> Result<ContaminationChecker> {
+        if self.threshold.is_nan() {
+            return Err(Error::InvalidProportion(self.threshold.to_string()));
+        }
+        if self.threshold <= 0.0 {
+            return Err(Error::ProportionTooLow(self.threshold.to_string()));
+        }
+        if self.threshold > 1.0 {
+            return Err(Error::ProportionTooHigh(self.threshold.to_string()));
+        }
+        let manifest_path = Manifest::path(&self.path)?;
+        let (manifest, dir) = Manifest::find(&manifest_path)?;
+        for split in &self.splits {
+            if !manifest.splits.iter().any(|s| &s.name == split) {
+                return Err(Error::UnknownSplit(split.clone()));
+            }
+        }
+        let splits = match self.splits.is_empty() {
+            true => manifest.splits.iter().map(|s| s.name.clone()).collect(),
+            false => self.splits,
+        };
+        Ok(ContaminationChecker {
+            manifest,
+            manifest_path,
+            dir,
+            references: expand_inputs(&self.references)?,
+            reference: Reference {
+                keys: self.keys,
+                text: self.text,
+                ngram: self.ngram,
+                threshold: self.threshold,
+                exact: HashSet::new(),
+                ngrams: HashSet::new(),
+            },
+            splits,
+            remove: self.remove,
+            examples: self.examples,
+            reference_compression: self.reference_compression,
+            reference_has_header: self.reference_has_header
+                && self.reference_format.allows_header(),
+            reference_format: self.reference_format,
+        })
+    }
+}
+
+/// Checks the outputs of a previous run for rows which match a reference
+/// dataset, such as a public test set, optionally removing them.
+///
+/// Rows match if their key columns (or the whole row) are found exactly in
+/// the reference, or if enough of the word n-grams of their text column are
+/// found in the reference's text column. The reference is held in memory as
+/// hashes of its keys and n-grams.
+pub struct ContaminationChecker {
+    manifest: Manifest,
+    manifest_path: PathBuf,
+    /// The directory the manifest's paths are relative to.
+    dir: PathBuf,
+    /// The paths of the reference files
+    references: Vec<PathBuf>,
+    reference: Reference,
+    /// The names of the splits to check
+    splits: Vec<String>,
+    remove: bool,
+    examples: usize,
+    reference_compression: Compression,
+    reference_format: RecordFormat,
+    reference_has_header: bool,
+}
+
+impl ContaminationChecker {
+    /// Write a report of the rows in each split which match the reference,
+    /// removing them if asked to. Returns an error if any rows match and
+    /// weren't removed.
+    ///
+    /// Rewritten outputs, the manifest and the checksums are all written in
+    /// full before any of them replace the originals, so an error while
+    /// checking or writing them leaves the outputs as they were. They then
+    /// replace the originals one rename at a time, with the manifest and the
+    /// checksums last. If a rename fails, the outputs already replaced no
+    /// longer match the old manifest, which `ttv verify` reports.
+    pub fn run<W: Write>(mut self, mut out: W) -> Result<()> {
+        let rows = self.load_reference()?;
+        writeln!(
+            out,
+            "Loaded {} rows from {} reference file(s)",
+            rows,
+            self.references.len()
+        )?;
+
+        let files = OutputFiles::new(self.manifest.options.fsync, true);
+        let (total, rewritten) = match self.check_outputs(&mut out, &files) {
+            Ok(result) => result,
+            Err(e) => {
+                // Nothing has been committed yet, so this only removes the
+                // temporary files.
+                info!("Removing rewritten outputs after error");
+                files.remove_all();
+                return Err(e);
+            }
+        };
+        // The manifest and checksums come last, so they're only updated once
+        // every output they describe is in place.
+        for path in &rewritten {
+            files.commit(path)?;
+        }
+        out.flush()?;
+        match total {
+            0 => Ok(()),
+            _ if self.remove => Ok(()),
+            total => Err(Error::ContaminationFound(total)),
+        }
+    }
+
+    /// Check every chunk of the chosen splits, writing a report to `out`.
+    /// Chunks with rows to remove are rewritten to temporary files, along with
+    /// the updated manifest and checksums. Returns the number of matching rows
+    /// and the paths of the files to commit.
+    fn check_outputs<W: Write>(
+        &mut self,
+        out: &mut W,
+        files: &OutputFiles,
+    ) -> Result<(u64, Vec<PathBuf>)> {
+        let options = &self.manifest.options;
+        let gzip = match (self.remove, options.output_compression()) {
+            (true, Compression::GzipCompression) => Some(GzipCompressor::new(
+                if options.bgzf {
+                    GzipFormat::Bgzf
+                } else {
+                    GzipFormat::Gzip
+                },
+                options.compression_level.unwrap_or(6),
+                std::thread::available_parallelism().unwrap_or(NonZeroUsize::MIN),
+            )?),
+            _ => None,
+        };
+        let rewrite = Rewrite {
+            files,
+            gzip: gzip.as_ref(),
+        };
+
+        let mut outputs = OutputFormat {
+            compression: options.output_compression(),
+            format: options.format.clone(),
+            has_header: options.outputs_have_header(),
+        };
+        let mut total = 0;
+        let mut rewritten = Vec::new();
+        for split in &mut self.manifest.splits {
+            if !self.splits.contains(&split.name) {
+                continue;
+            }
+            let mut matches = Vec::new();
+            for chunk in &mut split.chunks {
+                let path = self.dir.join(&chunk.path);
+                let writer = self.remove.then_some(&rewrite);
+                let removed =
+                    self.reference
+                        .check_chunk(&path, chunk, &mut outputs, writer, &mut matches)?;
+                if removed > 0 {
+                    rewritten.push(path.clone());
+                    if chunk.index.is_some() {
+                        rewritten.push(index_path(&path));
+                    }
+                }
+            }
+            total += matches.len() as u64;
+            if self.remove {
+                let rows = split.rows;
+                split.rows = split.chunks.iter().map(|c| c.rows).sum();
+                split.bytes = split.chunks.iter().map(|c| c.bytes).sum();
+                writeln!(
+                    out,
+                    "{}: removed {} of {} rows matching the reference",
+                    split.name,
+                    matches.len(),
+                    rows
+                )?;
+            } else {
+                writeln!(
+                    out,
+                    "{}: {} of {} rows match the reference",
+                    split.name,
+                    matches.len(),
+                    split.rows
+                )?;
+            }
+            for (row, found) in matches.iter().take(self.examples) {
+                writeln!(out, "  {} ({})", row, found)?;
+            }
+        }
+
+        if !rewritten.is_empty() {
+            info!("Updating manifest {}", self.manifest_path.display());
+            self.manifest.write(files.create(&self.manifest_path)?)?;
+            rewritten.push(self.manifest_path.clone());
+            let checksums_path = Manifest::checksums_path(&self.manifest_path);
+            self.manifest
+                .write_checksums(files.create(&checksums_path)?)?;
+            rewritten.push(checksums_path);
+        }
+        Ok((total, rewritten))
+    }
+
+    /// Read every reference file, returning the number of rows read.
+    fn load_reference(&mut self) -> Result<u64> {
+        let mut rows = 0;
+        let mut format = self.reference_format.clone();
+        for path in &self.references {
+            info!("Reading reference rows from {}", path.display());
+            let checksum = SharedChecksum::default();
+            let mut reader = open_data(path, self.reference_compression, &mut format, &checksum)?;
+            let fields = self.reference.field_reader(&format)?;
+            let mut header = None;
+            if self.reference_has_header {
+                if let Some(line) = reader.read_line() {
+                    let line = line?;
+                    if let Some(fields) = &fields {
+                        header = Some(fields.fields(&line)?);
+                    }
+                }
+            }
+            let columns = self.reference.columns(header.as_deref())?;
+            while let Some(record) = reader.read_line() {
+                let record = record?;
+                let values = match &fields {
+                    Some(fields) => Some(fields.fields(&record)?),
+                    None => None,
+                };
+                self.reference.add(&columns, &record, values.as_deref());
+                rows += 1;
+            }
+        }
+        Ok(rows)
+    }
+}
+
+/// How the outputs of the run are read.
+struct OutputFormat {
+    compression: Compression,
+    format: RecordFormat,
+    has_header: bool,
+}
+
+/// Where to write outputs without their matching rows.
+struct Rewrite<'a> {
+    files: &'a OutputFiles,
+    gzip: Option<&'a GzipCompressor>,
+}
+
+/// Hashes of the keys and text n-grams of every reference row.
+struct Reference {
+    keys: Vec<Column>,
+    text: Option<Column>,
+    ngram: usize,
+    threshold: f64,
+    /// The hash of the key (or the whole row) of each reference row, if
+    /// matching exactly.
+    exact: HashSet<u128>,
+    /// The hash of every n-gram of the reference's text column.
+    ngrams: HashSet<u64>,
+}
+
+/// The indexes of the columns compared in one file.
+struct Columns {
+    keys: Vec<usize>,
+    text: Option<usize>,
+}
+
+/// Why a row matched the reference.
+enum Match {
+    Exact,
+    /// The proportion of the text's n-grams found in the reference.
+    Overlap(f64, usize),
+}
+
+impl fmt::Display for Match {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Match::Exact => write!(f, "exact match"),
+            Match::Overlap(overlap, n) => write!(f, "{:.0}% of {}-grams", overlap * 100.0, n),
+        }
+    }
+}
+
+impl Reference {
+    /// Whether rows are matched exactly, by their keys or as a whole.
+    fn matches_exactly(&self) -> bool {
+        !self.keys.is_empty() || self.text.is_none()
+    }
+
+    /// A reader for the fields of records of `format`, if columns are needed.
+    fn field_reader(&self, format: &RecordFormat) -> Result<Option<FieldReader>> {
+        if self.keys.is_empty() && self.text.is_none() {
+            return Ok(None);
+        }
+        match FieldReader::new(format) {
+            Some(fields) => Ok(Some(fields)),
+            None => Err(Error::ColumnsUnsupported),
+        }
+    }
+
+    fn columns(&self, header: Option<&[String]>) -> Result<Columns> {
+        Ok(Columns {
+            keys: self
+                .keys
+                .iter()
+                .map(|key| key.index(header))
+                .collect::<Result<_>>()?,
+            text: self.text.as_ref().map(|t| t.index(header)).transpose()?,
+        })
+    }
+
+    fn add(&mut self, columns: &Columns, record: &str, fields: Option<&[String]>) {
+        if self.matches_exactly() {
+            self.exact.insert(key_hash(columns, record, fields));
+        }
+        if let Some(text) = text_field(columns, fields) {
+            self.ngrams.extend(ngrams(text, self.ngram));
+        }
+    }
+
+    /// Whether a row matches the reference, and why.
+    fn check(&self, columns: &Columns, record: &str, fields: Option<&[String]>) -> Option<Match> {
+        if self.matches_exactly() && self.exact.contains(&key_hash(columns, record, fields)) {
+            return Some(Match::Exact);
+        }
+        let text = text_field(columns, fields)?;
+        let ngrams = ngrams(text, self.ngram);
+        if ngrams.is_empty() {
+            return None;
+        }
+        let found = ngrams.iter().filter(|n| self.ngrams.contains(n)).count();
+        let overlap = found as f64 / ngrams.len() as f64;
+        (overlap >= self.threshold).then_some(Match::Overlap(overlap, self.ngram))
+    }
+
+    /// Check every row of an output file, adding those which match to
+    /// `matches`. If `rewrite` is given, the other rows are written to a
+    /// temporary file to replace it, and the chunk's summary is updated.
+    ///
+    /// Returns the number of rows removed, in which case the file still needs
+    /// to be committed.
+    fn check_chunk(
+        &self,
+        path: &Path,
+        chunk: &mut ChunkSummary,
+        outputs: &mut OutputFormat,
+        rewrite: Option<&Rewrite>,
+        matches: &mut Vec<(String, Match)>,
+    ) -> Result<u64> {
+        info!("Checking {}", path.display());
+        let checksum = SharedChecksum::default();
+        let mut reader = open_data(path, outputs.compression, &mut outputs.format, &checksum)?;
+        let fields = self.field_reader(&outputs.format)?;
+
+        let written = Arc::new(AtomicU64::new(0));
+        let output_checksum = SharedChecksum::default();
+        let mut output = match rewrite {
+            Some(rewrite) => {
+                let temp = rewrite.files.create(path)?;
+                let file = open_output(&temp, rewrite.gzip, &written, &output_checksum)?;
+                let index = chunk.index.is_some().then(RowIndexWriter::default);
+                Some((rewrite, temp, file, index))
+            }
+            None => None,
+        };
+
+        let mut header = None;
+        if outputs.has_header {
+            if let Some(line) = reader.read_line() {
+                let line = line?;
+                if let Some((_, _, file, index)) = &mut output {
+                    file.write_all(line.as_bytes())?;
+                    if let Some(index) = index {
+                        index.header(line.len());
+                    }
+                }
+                if let Some(fields) = &fields {
+                    header = Some(fields.fields(&line)?);
+                }
+            }
+        }
+        let columns = self.columns(header.as_deref())?;
+
+        let mut removed = 0;
+        while let Some(record) = reader.read_line() {
+            let record = record?;
+            let values = match &fields {
+                Some(fields) => Some(fields.fields(&record)?),
+                None => None,
+            };
+            match self.check(&columns, &record, values.as_deref()) {
+                Some(found) => {
+                    matches.push((preview(&record), found));
+                    removed += 1;
+                }
+                None => {
+                    if let Some((_, _, file, index)) = &mut output {
+                        file.write_all(record.as_bytes())?;
+                        if let Some(index) = index {
+                            index.row(record.len());
+                        }
+                    }
+                }
+            }
+        }
+
+        let (rewrite, temp, file, index) = match output {
+            Some(output) => output,
+            None => return Ok(0),
+        };
+        file.finish()?;
+        if removed == 0 {
+            // Nothing changed, so keep the original file.
+            remove_file(&temp)?;
+            return Ok(0);
+        }
+        if let Some(index) = index {
+            let index_path = index_path(path);
+            index.write(&temp, rewrite.files.create(&index_path)?)?;
+        }
+        let (bytes, sha256) = output_checksum.lock().unwrap().digest();
+        chunk.rows -= removed;
+        chunk.bytes = bytes;
+        chunk.sha256 = sha256;
+        Ok(removed)
+    }
+}
+
+/// Hash the key columns of a row, or the whole row if there are none, in the
+/// same way as keys are hashed to check for leakage.
+fn key_hash(columns: &Columns, record: &str, fields: Option<&[String]>) -> u128 {
+    match fields {
+        Some(fields) if !columns.keys.is_empty() => {
+            let key: Vec<&str> = columns
+                .keys
+                .iter()
+                .map(|i| fields.get(*i).map_or("", String::as_str))
+                .collect();
+            hash_key(&key)
+        }
+        _ => hash_key(&[record.trim_end_matches(['\n', '\r'])]),
+    }
+}
+
+fn text_field<'a>(columns: &Columns, fields: Option<&'a [String]>) -> Option<&'a str> {
+    fields?.get(columns.text?).map(String::as_str)
+}
+
+/// Hash every run of `n` words in `text`, ignoring case and punctuation. A
+/// text of fewer than `n` words is a single n-gram.
+fn ngrams(text: &str, n: usize) -> Vec<u64> {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    if words.is_empty() {
+        return Vec::new();
+    }
+    words
+        .windows(n.min(words.len()))
+        .map(|ngram| {
+            let mut hasher = DefaultHasher::new();
+            ngram.hash(&mut hasher);
+            hasher.finish()
+        })
+        .collect()
+}
+
+/// A row, shortened to be printed on one line.
+fn preview(record: &str) -> String {
+    let line = record.trim_end_matches(['\n', '\r']);
+    match line.char_indices().nth(PREVIEW_CHARS) {
+        Some((end, _)) => format!("{}...", &line[..end]),
+        None => line.to_string(),
+    }
+}
@@ -0,0 +1,153 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+use crate::io::{expand_inputs, open_data, open_input, Compression, RecordFormat};
+
+/// Count the records in `inputs`, read in the same way as by `split`: the
+/// inputs are expanded, decompressed and divided into records, and the header
+/// of each one is skipped.
+///
+/// Plain text is counted by scanning for newlines, which is much faster than
+/// reading it line by line. If `cache` is true, the count of each file is
+/// saved in a hidden file next to it and reused while the file is unchanged.
+pub fn count<P: AsRef<Path>>(
+    inputs: &[P],
+    compression: Compression,
+    format: &RecordFormat,
+    has_header: bool,
+    cache: bool,
+) -> Result<u64> {
+    let has_header = has_header && format.allows_header();
+    let mut total = 0;
+    for path in expand_inputs(inputs)? {
+        let key = CountKey {
+            decompress: matches!(compression, Compression::GzipCompression),
+            format: format.clone(),
+            has_header,
+        };
+        let cached = match cache && path.to_str() != Some("-") {
+            true => CachedCount::read(&path, &key),
+            false => None,
+        };
+        let rows = match cached {
+            Some(rows) => {
+                info!("Using cached count for {}", path.display());
+                rows
+            }
+            None => {
+                info!("Counting records in {}", path.display());
+                let rows = count_file(&path, compression, format, has_header)?;
+                if cache && path.to_str() != Some("-") {
+                    if let Err(e) = CachedCount::write(&path, key, rows) {
+                        warn!("Couldn't cache count for {}: {}", path.display(), e);
+                    }
+                }
+                rows
+            }
+        };
+        total += rows;
+    }
+    Ok(total)
+}
+
+/// Count the rows in a single file, not including any header.
+fn count_file(
+    path: &Path,
+    compression: Compression,
+    format: &RecordFormat,
+    has_header: bool,
+) -> Result<u64> {
+    let checksum = Default::default();
+    let records = match format {
+        RecordFormat::Lines => count_lines(open_input(path, compression, &checksum)?)?,
+        _ => {
+            let mut reader = open_data(path, compression, &mut format.clone(), &checksum)?;
+            let mut records = 0;
+            while let Some(record) = reader.read_line() {
+                record?;
+                records += 1;
+            }
+            records
+        }
+    };
+    Ok(records.saturating_sub(has_header as u64))
+}
+
+/// Count the lines in `reader`, including any final line without a newline.
+fn count_lines(mut reader: Box<dyn Read>) -> Result<u64> {
+    let mut buf = vec![0; 1024 * 1024];
+    let mut lines = 0;
+    let mut last = b'\n';
+    loop {
+        let read = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        lines += memchr::memchr_iter(b'\n', &buf[..read]).count() as u64;
+        last = buf[read - 1];
+    }
+    Ok(lines + (last != b'\n') as u64)
+}
+
+/// The options which affect the count of a file.
+#[derive(Deserialize, Serialize)]
+struct CountKey {
+    decompress: bool,
+    format: RecordFormat,
+    has_header: bool,
+}
+
+/// The count of a file, cached next to it.
+#[derive(Deserialize, Serialize)]
+struct CachedCount {
+    /// The size and modification time of the file when it was counted.
+    bytes: u64,
+    modified: SystemTime,
+    key: CountKey,
+    rows: u64,
+}
+
+impl CachedCount {
+    /// The path of the cache for `path`, e.g. `.data.csv.count` for `data.csv`.
+    fn path(path: &Path) -> PathBuf {
+        let mut name = std::ffi::OsString::from(".");
+        name.push(path.file_name().unwrap_or_default());
+        name.push(".count");
+        path.with_file_name(name)
+    }
+
+    /// Read the cached count for `path`, if there is one and the file and
+    /// options haven't changed since.
+    fn read(path: &Path, key: &CountKey) -> Option<u64> {
+        let file = File::open(Self::path(path)).ok()?;
+        let cached: CachedCount = serde_json::from_reader(BufReader::new(file)).ok()?;
+        let metadata = path.metadata().ok()?;
+        // Compare the options as JSON, since formats can't be compared directly.
+        let unchanged = cached.bytes == metadata.len()
+            && Some(cached.modified) == metadata.modified().ok()
+            && serde_json::to_value(&cached.key).ok()? == serde_json::to_value(key).ok()?;
+        unchanged.then_some(cached.rows)
+    }
+
+    fn write(path: &Path, key: CountKey, rows: u64) -> Result<()> {
+        let metadata = path.metadata()?;
+        let cached = CachedCount {
+            bytes: metadata.len(),
+            modified: metadata.modified()?,
+            key,
+            rows,
+        };
+        let mut file = BufWriter::new(File::create(Self::path(path))?);
+        serde_json::to_writer(&mut file, &cached).map_err(std::io::Error::from)?;
+        file.flush()?;
+        Ok(())
+    }
+}
@@ -7,11 +7,74 @@ use crate::split::ProportionSplit;
 pub enum Error {
     #[error("empty file")]
     EmptyFile,
+    #[error("invalid record: {0}")]
+    InvalidRecord(String),
+    #[error("invalid record separator: {0}")]
+    InvalidRecordSeparator(String),
+    #[error("no inputs given")]
+    NoInputs,
+    #[error("no input files found matching {0}")]
+    NoInputFiles(String),
+    #[error("invalid glob pattern: {0}")]
+    InvalidGlob(String),
+    #[error("stdin ('-') can't be combined with other inputs")]
+    StdinWithOtherInputs,
+    #[error("header of {0} doesn't match the header of the first input")]
+    HeaderMismatch(String),
+    #[error("invalid output template: {0}")]
+    InvalidOutputTemplate(String),
+    #[error("more than one chunk would be written to {0}")]
+    DuplicateOutputPath(String),
+    #[error("shuffled outputs can't be limited by their compressed size")]
+    ShuffleWithCompressedChunkBytes,
+    #[error("--total-rows is required for contiguous shards of proportion splits")]
+    ShardsRequireTotalRows,
+    #[error("an output prefix is required when reading from stdin or multiple input files")]
+    OutputPrefixRequired,
+    #[error("no column {0} in the header")]
+    UnknownColumn(String),
+    #[error("a row of {1} has no column {0}")]
+    MissingColumn(String, String),
+    #[error("column {0} must be given by position, since there is no header")]
+    ColumnNameWithoutHeader(String),
+    #[error("columns can only be chosen for CSV or plain text records")]
+    ColumnsUnsupported,
+    #[error("{0} key(s) appear in more than one split")]
+    LeakageFound(u64),
+    #[error("no split named {0} in the manifest")]
+    UnknownSplit(String),
+    #[error("{0} row(s) match the reference")]
+    ContaminationFound(u64),
+    #[error("no manifest found in {0}")]
+    NoManifest(String),
+    #[error("more than one manifest in {0}, give the path of the one to use")]
+    AmbiguousManifest(String),
+    #[error("invalid row index: {0}")]
+    InvalidIndex(String),
+    #[error("output doesn't match the manifest: {0}")]
+    OutputMismatch(String),
+    #[error("{0} output file(s) failed verification")]
+    VerificationFailed(usize),
+    #[error("invalid job file {0}: {1}")]
+    InvalidJobFile(String, String),
+    #[error("no job named {0} in the job file")]
+    UnknownJob(String),
+    #[error("invalid override: {0}, expected an option such as --seed 42")]
+    InvalidOverride(String),
     #[error("invalid split specification: {0}")]
     InvalidSplitSpecification(String),
     #[error("invalid splits: {0:?}")]
     InvalidSplits(Vec<ProportionSplit>),
 
+    #[error("invalid character: {0}, expected a single ASCII character")]
+    InvalidCharacter(String),
+    #[error("invalid size: {0}, expected a number of bytes such as 1024, 64K or 256M")]
+    InvalidSize(String),
+    #[error("invalid compression level: {0}, expected 0 to 9")]
+    InvalidCompressionLevel(u32),
+
+    #[error("invalid proportion: {0}, expected a number between 0 and 1")]
+    InvalidProportion(String),
     #[error("proportion too low: {0}")]
     ProportionTooLow(String),
     #[error("proportion too high: {0}")]
@@ -25,6 +88,16 @@ pub enum Error {
     ParseFloatError(std::num::ParseFloatError),
     #[error("error parsing int: {0}")]
     ParseIntError(std::num::ParseIntError),
+    #[error("{0} already exists, use --force to overwrite it")]
+    OutputExists(String),
+    #[error("invalid run directory: {0}, expected 'timestamp' or 'seed'")]
+    InvalidRunDir(String),
+    #[error("the run was aborted after an error")]
+    RunAborted,
+    #[error("a writer thread stopped unexpectedly")]
+    WriterStopped,
+    #[error("a writer thread panicked")]
+    WriterPanicked,
     #[error("internal error: {0}")]
     SendError(std::sync::mpsc::SendError<String>),
 }
@@ -1,13 +1,43 @@
 use std::fs::File;
-use std::io::{BufReader, Read, Write};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::num::NonZeroUsize;
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
-use flate2::read::GzDecoder;
-use flate2::write::GzEncoder;
+use flate2::bufread::GzDecoder;
+use serde::{Deserialize, Serialize};
 
 use crate::error::Result;
 
-pub type OutputWriter = Box<dyn Write>;
+mod bgzf;
+mod checksum;
+mod dialect;
+mod fields;
+mod gzip;
+mod inputs;
+mod records;
+mod temp;
+
+pub use self::bgzf::{index_path, BgzfReader, RowIndexWriter};
+pub use self::checksum::{Checksum, HashingReader, HashingWriter, SharedChecksum};
+pub use self::dialect::{CsvDialect, CsvReader};
+pub use self::fields::{Column, FieldReader};
+pub use self::gzip::{GzipCompressor, GzipFormat, MAX_COMPRESSION_LEVEL};
+pub use self::inputs::{expand_inputs, MultiReader};
+pub use self::records::{
+    FastaReader, FastqReader, MultiLineReader, RecordSeparator, SeparatedReader,
+};
+pub use self::temp::TempDir;
+
+pub type OutputWriter = Box<dyn FinishWrite>;
+
+/// A writer which must be explicitly finished, so that any errors writing the
+/// end of the output (such as a gzip trailer) aren't lost when it's dropped.
+pub trait FinishWrite: Write {
+    /// Write any remaining output and flush it to the underlying file.
+    fn finish(self: Box<Self>) -> std::io::Result<()>;
+}
 
 #[derive(Clone, Copy, Debug)]
 pub enum Compression {
@@ -15,33 +45,50 @@ pub enum Compression {
     GzipCompression,
 }
 
-pub trait LineReader {
-    fn read_line(&mut self) -> Option<Result<String>>;
+/// How the input is divided into records, each of which is assigned to a
+/// single split.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RecordFormat {
+    /// One record per line.
+    Lines,
+    /// CSV records, which may contain embedded newlines.
+    Csv(CsvDialect),
+    /// FASTA records: a `>` header line followed by any number of sequence
+    /// lines.
+    Fasta,
+    /// FASTQ records of four lines each.
+    Fastq,
+    /// Records made up of a fixed number of lines.
+    MultiLine(NonZeroUsize),
+    /// Plain text records divided by a custom separator.
+    Separated(RecordSeparator),
 }
 
-impl LineReader for csv::Reader<Box<dyn Read>> {
-    fn read_line(&mut self) -> Option<Result<String>> {
-        let mut record = csv::ByteRecord::with_capacity(1024, 100);
-        match self.read_byte_record(&mut record) {
-            Ok(read) if read => {
-                let curs = std::io::Cursor::new(Vec::with_capacity(1024));
-                let mut writer = csv::Writer::from_writer(curs);
-                writer.write_byte_record(&record).unwrap();
-                let s = String::from_utf8(writer.into_inner().unwrap().into_inner()).unwrap();
-                Some(Ok(s))
-            }
-            Ok(_) => None,
-            Err(e) => Some(Err(e.into())),
-        }
+impl RecordFormat {
+    /// Whether files in this format can have a header row.
+    pub fn allows_header(&self) -> bool {
+        !matches!(self, RecordFormat::Fasta | RecordFormat::Fastq)
     }
 }
 
+pub trait LineReader {
+    fn read_line(&mut self) -> Option<Result<String>>;
+}
+
 impl LineReader for BufReader<Box<dyn Read>> {
     fn read_line(&mut self) -> Option<Result<String>> {
         let mut buf = String::with_capacity(1024);
         match std::io::BufRead::read_line(self, &mut buf) {
             Ok(0) => None,
-            Ok(_) => Some(Ok(buf)),
+            Ok(_) => {
+                // Make sure the final line of a file is terminated, so it
+                // isn't joined to the next row written to the same output.
+                if !buf.ends_with('\n') {
+                    buf.push('\n');
+                }
+                Some(Ok(buf))
+            }
             Err(e) => Some(Err(e.into())),
         }
     }
@@ -50,32 +97,178 @@ impl LineReader for BufReader<Box<dyn Read>> {
 pub fn open_data<P: AsRef<Path>>(
     path: P,
     compression: Compression,
-    csv_builder: Option<csv::ReaderBuilder>,
+    format: &mut RecordFormat,
+    checksum: &SharedChecksum,
 ) -> Result<Box<dyn LineReader>> {
+    record_reader(open_input(path, compression, checksum)?, format)
+}
+
+/// Whether the file at `path` is gzip compressed, judging by its first two
+/// bytes. Stdin can't be inspected, so is taken to be uncompressed.
+pub fn detect_compression<P: AsRef<Path>>(path: P) -> Result<Compression> {
+    let path = path.as_ref();
+    if path.to_str() == Some("-") {
+        return Ok(Compression::Uncompressed);
+    }
+    let mut magic = Vec::with_capacity(2);
+    File::open(path)?.take(2).read_to_end(&mut magic)?;
+    match magic[..] {
+        [0x1f, 0x8b] => Ok(Compression::GzipCompression),
+        _ => Ok(Compression::Uncompressed),
+    }
+}
+
+/// Open a file or stdin for reading, decompressing it if needed.
+///
+/// `checksum` is updated with the size and checksum of the raw input.
+pub fn open_input<P: AsRef<Path>>(
+    path: P,
+    compression: Compression,
+    checksum: &SharedChecksum,
+) -> Result<Box<dyn Read>> {
     // Read from stdin if input is '-', else try to open the provided file.
     let reader: Box<dyn Read> = match path.as_ref().to_str() {
         Some("-") => Box::new(std::io::stdin()),
         Some(p) => Box::new(File::open(p)?),
         _ => unreachable!(),
     };
+    let reader: Box<dyn Read> = Box::new(HashingReader::new(reader, Arc::clone(checksum)));
 
     let reader: Box<dyn Read> = match compression {
         Compression::Uncompressed => reader,
-        Compression::GzipCompression => Box::new(GzDecoder::new(reader)),
+        Compression::GzipCompression => Box::new(MultiGzDecoder::new(BufReader::new(reader))),
     };
+    Ok(reader)
+}
 
-    let reader: Box<dyn LineReader> = match csv_builder {
-        Some(builder) => Box::new(builder.from_reader(reader)),
-        None => Box::new(BufReader::with_capacity(1024 * 1024, reader)),
+/// Read records of the given format from already decompressed data.
+fn record_reader(reader: Box<dyn Read>, format: &mut RecordFormat) -> Result<Box<dyn LineReader>> {
+    let reader: Box<dyn LineReader> = match format {
+        RecordFormat::Csv(dialect) => Box::new(CsvReader::new(reader, dialect)?),
+        RecordFormat::Lines => Box::new(BufReader::with_capacity(1024 * 1024, reader)),
+        RecordFormat::Fasta => Box::new(FastaReader::new(BufReader::with_capacity(
+            1024 * 1024,
+            reader,
+        ))),
+        RecordFormat::Fastq => Box::new(FastqReader::new(BufReader::with_capacity(
+            1024 * 1024,
+            reader,
+        ))),
+        RecordFormat::MultiLine(n) => Box::new(MultiLineReader::new(
+            BufReader::with_capacity(1024 * 1024, reader),
+            n.get(),
+        )),
+        RecordFormat::Separated(separator) => {
+            Box::new(SeparatedReader::new(reader, separator.clone()))
+        }
     };
     Ok(reader)
 }
 
-pub fn open_output<P: AsRef<Path>>(path: P, compression: Compression) -> Result<OutputWriter> {
-    let file = File::create(path)?;
-    let writer: OutputWriter = match compression {
-        Compression::GzipCompression => Box::new(GzEncoder::new(file, Default::default())),
-        Compression::Uncompressed => Box::new(file),
+/// Decodes every member of a (possibly multi-member) gzip stream, such as those
+/// produced by `pigz`, `bgzip` or `cat a.gz b.gz`.
+///
+/// Unlike `flate2::read::MultiGzDecoder`, any trailing bytes which don't look
+/// like the start of another gzip member are reported as an error rather than
+/// being silently dropped.
+struct MultiGzDecoder<R: BufRead> {
+    decoder: Option<GzDecoder<R>>,
+    /// Number of members decoded so far, for error reporting.
+    members: u64,
+}
+
+impl<R: BufRead> MultiGzDecoder<R> {
+    fn new(reader: R) -> Self {
+        MultiGzDecoder {
+            decoder: Some(GzDecoder::new(reader)),
+            members: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Read for MultiGzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let decoder = match self.decoder.as_mut() {
+                Some(d) => d,
+                None => return Ok(0),
+            };
+            let read = decoder.read(buf)?;
+            if read > 0 {
+                return Ok(read);
+            }
+
+            // The current member is finished; check whether there's another.
+            self.members += 1;
+            let mut inner = self.decoder.take().unwrap().into_inner();
+            let remaining = inner.fill_buf()?;
+            match remaining.first() {
+                None => return Ok(0),
+                Some(0x1f) => self.decoder = Some(GzDecoder::new(inner)),
+                Some(_) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!(
+                            "trailing data after gzip member {} is not gzip compressed",
+                            self.members
+                        ),
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// Open a file for writing, gzip compressed with `gzip` if given.
+///
+/// `bytes_written` is updated with the number of bytes written to the file
+/// itself (i.e. after compression), and `checksum` with their checksum.
+pub fn open_output<P: AsRef<Path>>(
+    path: P,
+    gzip: Option<&GzipCompressor>,
+    bytes_written: &Arc<AtomicU64>,
+    checksum: &SharedChecksum,
+) -> Result<OutputWriter> {
+    let file = CountingWriter {
+        inner: HashingWriter::new(File::create(path)?, Arc::clone(checksum)),
+        count: Arc::clone(bytes_written),
+    };
+    let writer: OutputWriter = match gzip {
+        Some(gzip) => gzip.encoder(file),
+        None => Box::new(file),
     };
     Ok(writer)
 }
+
+impl FinishWrite for std::io::Stdout {
+    fn finish(mut self: Box<Self>) -> std::io::Result<()> {
+        self.flush()
+    }
+}
+
+/// Counts the bytes written to the inner writer.
+struct CountingWriter<W> {
+    inner: W,
+    count: Arc<AtomicU64>,
+}
+
+impl<W: Write> FinishWrite for CountingWriter<W> {
+    fn finish(mut self: Box<Self>) -> std::io::Result<()> {
+        self.flush()
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count.fetch_add(written as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
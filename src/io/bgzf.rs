@@ -0,0 +1,260 @@
+//! BGZF output, and a row index for reading ranges of rows from it.
+//!
+//! BGZF is gzip made up of blocks of at most 64KiB, each of which records its
+//! compressed size, as used by `bgzip`. A position in the file is given by a
+//! virtual offset: the offset of the start of a block in the compressed file,
+//! shifted left 16 bits, plus the offset within that block's uncompressed data.
+//!
+//! The row index written next to each output is made up of little-endian
+//! `u64`s: the magic number, the number of rows between index entries, the
+//! number of rows, the length of any header in bytes, and then the virtual
+//! offset of the first of every `interval` rows.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use flate2::write::DeflateEncoder;
+use flate2::Crc;
+
+use super::{record_reader, MultiGzDecoder, RecordFormat};
+use crate::error::{Error, Result};
+
+/// The maximum amount of uncompressed data in each block, which leaves room
+/// for the block to grow when compressed without going over 64KiB.
+pub const BLOCK_SIZE: usize = 0xff00;
+
+/// The start of every BGZF block, up to the block's size: a gzip header with
+/// an extra field holding a `BC` subfield.
+const BLOCK_HEADER: [u8; 16] = [
+    0x1f, 0x8b, 8, 4, 0, 0, 0, 0, 0, 0xff, 6, 0, b'B', b'C', 2, 0,
+];
+
+/// The empty block which marks the end of a BGZF file.
+pub const EOF_BLOCK: [u8; 28] = [
+    0x1f, 0x8b, 8, 4, 0, 0, 0, 0, 0, 0xff, 6, 0, b'B', b'C', 2, 0, 0x1b, 0, 3, 0, 0, 0, 0, 0, 0, 0,
+    0, 0,
+];
+
+/// Identifies a row index, and its version.
+const INDEX_MAGIC: u64 = u64::from_le_bytes(*b"TTVROWS\x01");
+
+/// The number of rows between index entries.
+const INDEX_INTERVAL: u64 = 64;
+
+/// Compress a block of at most [`BLOCK_SIZE`] bytes as a BGZF block.
+pub fn compress_block(level: flate2::Compression, block: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::with_capacity(block.len() / 2 + 64), level);
+    encoder.write_all(block)?;
+    let deflated = encoder.finish()?;
+    let mut crc = Crc::new();
+    crc.update(block);
+
+    let size = BLOCK_HEADER.len() + 2 + deflated.len() + 8;
+    let bsize =
+        u16::try_from(size - 1).map_err(|_| io::Error::other("BGZF block is larger than 64KiB"))?;
+    let mut compressed = Vec::with_capacity(size);
+    compressed.extend_from_slice(&BLOCK_HEADER);
+    compressed.extend_from_slice(&bsize.to_le_bytes());
+    compressed.extend_from_slice(&deflated);
+    compressed.extend_from_slice(&crc.sum().to_le_bytes());
+    compressed.extend_from_slice(&(block.len() as u32).to_le_bytes());
+    Ok(compressed)
+}
+
+/// Read the header and trailer of the next block, returning its compressed
+/// and uncompressed sizes and leaving the reader at the start of the next
+/// block.
+fn read_block_sizes<R: Read + Seek>(reader: &mut BufReader<R>) -> Result<(u64, u64)> {
+    let mut header = [0; 18];
+    reader.read_exact(&mut header)?;
+    if header[..BLOCK_HEADER.len()] != BLOCK_HEADER {
+        return Err(Error::InvalidIndex("output isn't BGZF".to_string()));
+    }
+    let size = u16::from_le_bytes([header[16], header[17]]) as u64 + 1;
+    reader.seek_relative(size as i64 - header.len() as i64 - 4)?;
+    let mut isize = [0; 4];
+    reader.read_exact(&mut isize)?;
+    Ok((size, u32::from_le_bytes(isize) as u64))
+}
+
+/// The path of the row index for the BGZF file at `path`.
+pub fn index_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut index = path.as_ref().as_os_str().to_owned();
+    index.push(".idx");
+    PathBuf::from(index)
+}
+
+/// Records where rows start in the uncompressed data as they're written, to
+/// build a row index once the BGZF file is complete.
+#[derive(Default)]
+pub struct RowIndexWriter {
+    header_bytes: u64,
+    /// The number of uncompressed bytes written so far.
+    position: u64,
+    rows: u64,
+    /// The uncompressed offset of the first of every `INDEX_INTERVAL` rows.
+    offsets: Vec<u64>,
+}
+
+impl RowIndexWriter {
+    /// Record that a header of `len` bytes was written.
+    pub fn header(&mut self, len: usize) {
+        self.header_bytes += len as u64;
+        self.position += len as u64;
+    }
+
+    /// Record that a row of `len` bytes was written.
+    pub fn row(&mut self, len: usize) {
+        if self.rows.is_multiple_of(INDEX_INTERVAL) {
+            self.offsets.push(self.position);
+        }
+        self.rows += 1;
+        self.position += len as u64;
+    }
+
+    /// Write the index for the complete BGZF file at `bgzf` to `path`.
+    pub fn write<P: AsRef<Path>, Q: AsRef<Path>>(self, bgzf: P, path: Q) -> Result<()> {
+        // Find the block containing each indexed row from the block sizes.
+        let mut blocks = BufReader::new(File::open(bgzf)?);
+        let mut voffsets = Vec::with_capacity(self.offsets.len());
+        let mut offsets = self.offsets.iter().peekable();
+        let (mut block_start, mut block_data_start) = (0, 0);
+        while offsets.peek().is_some() {
+            let (size, data_size) = read_block_sizes(&mut blocks)?;
+            while let Some(&&offset) = offsets.peek() {
+                if offset >= block_data_start + data_size {
+                    break;
+                }
+                voffsets.push(block_start << 16 | (offset - block_data_start));
+                offsets.next();
+            }
+            block_start += size;
+            block_data_start += data_size;
+        }
+
+        let mut file = BufWriter::new(File::create(path)?);
+        for value in [INDEX_MAGIC, INDEX_INTERVAL, self.rows, self.header_bytes]
+            .into_iter()
+            .chain(voffsets)
+        {
+            file.write_all(&value.to_le_bytes())?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads ranges of rows from a BGZF output, using its row index to avoid
+/// decompressing the whole file.
+///
+/// ```no_run
+/// # fn main() -> ttv::Result<()> {
+/// use ttv::{BgzfReader, RecordFormat};
+///
+/// let reader = BgzfReader::open("train/data.train.csv.gz", RecordFormat::Lines)?;
+/// let rows = reader.read_rows(1000..1010)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct BgzfReader {
+    path: PathBuf,
+    format: RecordFormat,
+    interval: u64,
+    rows: u64,
+    header_bytes: u64,
+    /// The virtual offset of the first of every `interval` rows.
+    voffsets: Vec<u64>,
+}
+
+impl BgzfReader {
+    /// Open a BGZF output and the row index written next to it, reading records
+    /// of the given format.
+    pub fn open<P: AsRef<Path>>(path: P, format: RecordFormat) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut index = Vec::new();
+        File::open(index_path(&path))?.read_to_end(&mut index)?;
+        let mut values = index
+            .chunks_exact(8)
+            .map(|v| u64::from_le_bytes(v.try_into().unwrap()));
+        if !index.len().is_multiple_of(8) || values.next() != Some(INDEX_MAGIC) {
+            return Err(Error::InvalidIndex("not a ttv row index".to_string()));
+        }
+        let (interval, rows, header_bytes) = match (values.next(), values.next(), values.next()) {
+            (Some(interval), Some(rows), Some(header_bytes)) if interval > 0 => {
+                (interval, rows, header_bytes)
+            }
+            _ => return Err(Error::InvalidIndex("truncated header".to_string())),
+        };
+        let voffsets: Vec<u64> = values.collect();
+        if voffsets.len() as u64 != rows.div_ceil(interval) {
+            return Err(Error::InvalidIndex(format!(
+                "expected {} entries, found {}",
+                rows.div_ceil(interval),
+                voffsets.len()
+            )));
+        }
+        Ok(BgzfReader {
+            path,
+            format,
+            interval,
+            rows,
+            header_bytes,
+            voffsets,
+        })
+    }
+
+    /// The number of rows in the file, not including any header.
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    /// Read the header at the start of the file, if it has one.
+    pub fn header(&self) -> Result<Option<String>> {
+        if self.header_bytes == 0 {
+            return Ok(None);
+        }
+        let mut header = String::new();
+        self.reader_at(0)?
+            .take(self.header_bytes)
+            .read_to_string(&mut header)?;
+        Ok(Some(header))
+    }
+
+    /// Read the rows in `rows`, numbered from zero after any header. The range
+    /// is cut short at the end of the file.
+    pub fn read_rows(&self, rows: Range<u64>) -> Result<Vec<String>> {
+        let (start, end) = (rows.start, rows.end.min(self.rows));
+        if start >= end {
+            return Ok(Vec::new());
+        }
+        let entry = start / self.interval;
+        let reader = self.reader_at(self.voffsets[entry as usize])?;
+        let mut reader = record_reader(reader, &mut self.format.clone())?;
+
+        // Skip from the indexed row to the first one wanted.
+        for _ in entry * self.interval..start {
+            if let Some(record) = reader.read_line() {
+                record?;
+            }
+        }
+        let mut records = Vec::with_capacity((end - start) as usize);
+        for _ in start..end {
+            match reader.read_line() {
+                Some(record) => records.push(record?),
+                None => break,
+            }
+        }
+        Ok(records)
+    }
+
+    /// Decompress the file from a virtual offset.
+    fn reader_at(&self, voffset: u64) -> Result<Box<dyn Read>> {
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(voffset >> 16))?;
+        let mut reader = MultiGzDecoder::new(BufReader::new(file));
+        io::copy(&mut (&mut reader).take(voffset & 0xffff), &mut io::sink())?;
+        Ok(Box::new(reader))
+    }
+}
@@ -0,0 +1,112 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use sha2::{Digest, Sha256};
+
+use crate::error::Result;
+
+/// The size and SHA-256 checksum of a file, computed as it is read.
+#[derive(Default)]
+pub struct Checksum {
+    bytes: u64,
+    hasher: Sha256,
+    /// Whether the whole file has been read.
+    complete: bool,
+}
+
+/// A checksum which is updated by a reader on another thread or deep inside a
+/// stack of decoders.
+pub type SharedChecksum = Arc<Mutex<Checksum>>;
+
+impl Checksum {
+    /// The number of bytes hashed so far, and their checksum.
+    pub fn digest(&self) -> (u64, String) {
+        (self.bytes, to_hex(&self.hasher.clone().finalize()))
+    }
+
+    /// Read the rest of the file at `path`, after any bytes already read, and
+    /// return its total size and checksum.
+    ///
+    /// This is needed if reading stopped early, e.g. once every row split was
+    /// full.
+    pub fn finish<P: AsRef<Path>>(checksum: &SharedChecksum, path: P) -> Result<(u64, String)> {
+        let (complete, offset) = {
+            let checksum = checksum.lock().unwrap();
+            (checksum.complete, checksum.bytes)
+        };
+        if !complete {
+            // Anything buffered by the readers has already been hashed, so
+            // carry on from the last byte taken from the file.
+            let rest: Box<dyn Read> = match path.as_ref().to_str() {
+                Some("-") => Box::new(std::io::stdin()),
+                _ => {
+                    let mut file = File::open(path)?;
+                    file.seek(SeekFrom::Start(offset))?;
+                    Box::new(file)
+                }
+            };
+            let mut reader = HashingReader::new(rest, Arc::clone(checksum));
+            std::io::copy(&mut reader, &mut std::io::sink())?;
+        }
+        let digest = checksum.lock().unwrap().digest();
+        Ok(digest)
+    }
+}
+
+/// Updates a checksum with every byte read through it.
+pub struct HashingReader<R> {
+    inner: R,
+    checksum: SharedChecksum,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R, checksum: SharedChecksum) -> Self {
+        HashingReader { inner, checksum }
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        let mut checksum = self.checksum.lock().unwrap();
+        if read == 0 && !buf.is_empty() {
+            checksum.complete = true;
+        }
+        checksum.bytes += read as u64;
+        checksum.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// Updates a checksum with every byte written through it.
+pub struct HashingWriter<W> {
+    inner: W,
+    checksum: SharedChecksum,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W, checksum: SharedChecksum) -> Self {
+        HashingWriter { inner, checksum }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        let mut checksum = self.checksum.lock().unwrap();
+        checksum.bytes += written as u64;
+        checksum.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Format bytes, such as a digest, as lowercase hexadecimal.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
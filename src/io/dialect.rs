@@ -0,0 +1,234 @@
+use std::io::{BufRead, BufReader, Read};
+
+use log::info;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::LineReader;
+use crate::error::{Error, Result};
+
+/// Delimiters considered when sniffing the dialect of a CSV file.
+const SNIFF_DELIMITERS: [u8; 4] = [b',', b'\t', b';', b'|'];
+
+/// Maximum number of lines inspected when sniffing the dialect of a CSV file.
+const SNIFF_LINES: usize = 20;
+
+/// The dialect used to read CSV input and to write rows back out.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CsvDialect {
+    /// The field delimiter.
+    #[serde(
+        serialize_with = "serialize_byte",
+        deserialize_with = "deserialize_byte"
+    )]
+    pub delimiter: u8,
+    /// The quote character.
+    #[serde(
+        serialize_with = "serialize_byte",
+        deserialize_with = "deserialize_byte"
+    )]
+    pub quote: u8,
+    /// The escape character for quotes, if quotes aren't escaped by doubling.
+    #[serde(
+        serialize_with = "serialize_optional_byte",
+        deserialize_with = "deserialize_optional_byte"
+    )]
+    pub escape: Option<u8>,
+    /// Whether quotes inside quoted fields are escaped by doubling them.
+    pub double_quote: bool,
+    /// Lines starting with this character are skipped.
+    #[serde(
+        serialize_with = "serialize_optional_byte",
+        deserialize_with = "deserialize_optional_byte"
+    )]
+    pub comment: Option<u8>,
+    /// The record terminator. If `None`, any of `\r`, `\n` or `\r\n` is
+    /// accepted when reading and `\n` is used when writing.
+    #[serde(
+        serialize_with = "serialize_optional_byte",
+        deserialize_with = "deserialize_optional_byte"
+    )]
+    pub terminator: Option<u8>,
+    /// Detect the delimiter from the start of the input, rather than using
+    /// `delimiter`.
+    pub sniff: bool,
+}
+
+/// Serialize a byte as a character, rather than a number.
+fn serialize_byte<S: Serializer>(byte: &u8, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_char(*byte as char)
+}
+
+fn serialize_optional_byte<S: Serializer>(
+    byte: &Option<u8>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match byte {
+        Some(byte) => serializer.serialize_some(&(*byte as char)),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Deserialize a byte from a single ASCII character.
+fn deserialize_byte<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<u8, D::Error> {
+    let c = char::deserialize(deserializer)?;
+    u8::try_from(c).ok().filter(u8::is_ascii).ok_or_else(|| {
+        serde::de::Error::custom(format!("expected an ASCII character, found {c:?}"))
+    })
+}
+
+fn deserialize_optional_byte<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<u8>, D::Error> {
+    #[derive(Deserialize)]
+    struct Byte(#[serde(deserialize_with = "deserialize_byte")] u8);
+    Ok(Option::<Byte>::deserialize(deserializer)?.map(|b| b.0))
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        CsvDialect {
+            delimiter: b',',
+            quote: b'"',
+            escape: None,
+            double_quote: true,
+            comment: None,
+            terminator: None,
+            sniff: false,
+        }
+    }
+}
+
+impl CsvDialect {
+    pub(crate) fn reader_builder(&self) -> csv::ReaderBuilder {
+        let mut builder = csv::ReaderBuilder::new();
+        builder
+            .has_headers(false)
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .escape(self.escape)
+            .double_quote(self.double_quote)
+            .comment(self.comment);
+        if let Some(terminator) = self.terminator {
+            builder.terminator(csv::Terminator::Any(terminator));
+        }
+        builder
+    }
+
+    fn writer_builder(&self) -> csv::WriterBuilder {
+        let mut builder = csv::WriterBuilder::new();
+        builder
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .double_quote(self.double_quote)
+            .comment(self.comment);
+        if let Some(escape) = self.escape {
+            builder.escape(escape);
+        }
+        if let Some(terminator) = self.terminator {
+            builder.terminator(csv::Terminator::Any(terminator));
+        }
+        builder
+    }
+
+    /// Guess the delimiter from a sample of the start of the data.
+    ///
+    /// Picks the candidate delimiter which appears the same, non-zero number
+    /// of times (outside of quotes) on the most lines, falling back to the
+    /// current delimiter if none do.
+    fn sniff_delimiter(&self, sample: &[u8]) -> u8 {
+        let terminator = self.terminator.unwrap_or(b'\n');
+        let mut lines: Vec<&[u8]> = sample.split(|b| *b == terminator).collect();
+        // The last line is probably incomplete, unless it's the only one.
+        if lines.len() > 1 {
+            lines.pop();
+        }
+        lines.truncate(SNIFF_LINES);
+
+        let mut best = (self.delimiter, 0);
+        for delimiter in SNIFF_DELIMITERS {
+            let counts: Vec<usize> = lines
+                .iter()
+                .map(|line| {
+                    let mut in_quotes = false;
+                    line.iter()
+                        .filter(|b| {
+                            if **b == self.quote {
+                                in_quotes = !in_quotes;
+                            }
+                            !in_quotes && **b == delimiter
+                        })
+                        .count()
+                })
+                .collect();
+            let first = match counts.first() {
+                Some(c) if *c > 0 => *c,
+                _ => continue,
+            };
+            let consistent = counts.iter().filter(|c| **c == first).count();
+            if consistent > best.1 {
+                best = (delimiter, consistent);
+            }
+        }
+        best.0
+    }
+}
+
+/// Reads CSV records, writing each back out as a single row using the same
+/// dialect.
+pub struct CsvReader {
+    reader: csv::Reader<Box<dyn Read>>,
+    writer: csv::WriterBuilder,
+}
+
+impl CsvReader {
+    /// Create a reader for the given dialect.
+    ///
+    /// If the dialect asks for sniffing, the delimiter is detected from the
+    /// start of the data and the dialect updated, so that subsequent files
+    /// are read (and written) the same way.
+    pub fn new(reader: Box<dyn Read>, dialect: &mut CsvDialect) -> Result<Self> {
+        let reader: Box<dyn Read> = if dialect.sniff {
+            let mut reader = BufReader::with_capacity(64 * 1024, reader);
+            dialect.delimiter = dialect.sniff_delimiter(reader.fill_buf()?);
+            dialect.sniff = false;
+            info!("Sniffed CSV delimiter {:?}", dialect.delimiter as char);
+            Box::new(reader)
+        } else {
+            reader
+        };
+        Ok(CsvReader {
+            reader: dialect.reader_builder().from_reader(reader),
+            writer: dialect.writer_builder(),
+        })
+    }
+}
+
+impl LineReader for CsvReader {
+    fn read_line(&mut self) -> Option<Result<String>> {
+        let mut record = csv::ByteRecord::with_capacity(1024, 100);
+        match self.reader.read_byte_record(&mut record) {
+            Ok(read) if read => Some(self.write_record(&record)),
+            Ok(_) => None,
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+}
+
+impl CsvReader {
+    /// Write a record back out as a single row.
+    fn write_record(&self, record: &csv::ByteRecord) -> Result<String> {
+        let curs = std::io::Cursor::new(Vec::with_capacity(1024));
+        let mut writer = self.writer.from_writer(curs);
+        writer.write_byte_record(record)?;
+        let row = writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .into_inner();
+        String::from_utf8(row).map_err(|_| {
+            let line = record.position().map_or(0, |p| p.line());
+            Error::InvalidRecord(format!("row at line {line} isn't valid UTF-8"))
+        })
+    }
+}
@@ -0,0 +1,84 @@
+use std::fmt;
+use std::str::FromStr;
+
+use super::{CsvDialect, RecordFormat};
+use crate::error::{Error, Result};
+
+/// A column of the input, chosen by its name in the header or by its position,
+/// counting from 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Column {
+    Name(String),
+    Position(usize),
+}
+
+impl Column {
+    /// The index of the column in each row, given the fields of the header if
+    /// there is one.
+    pub fn index(&self, header: Option<&[String]>) -> Result<usize> {
+        match (self, header) {
+            (Column::Position(position), Some(header)) if *position > header.len() => {
+                Err(Error::UnknownColumn(position.to_string()))
+            }
+            (Column::Position(position), _) => Ok(position - 1),
+            (Column::Name(name), Some(header)) => header
+                .iter()
+                .position(|h| h == name)
+                .ok_or_else(|| Error::UnknownColumn(name.clone())),
+            (Column::Name(name), None) => Err(Error::ColumnNameWithoutHeader(name.clone())),
+        }
+    }
+}
+
+impl FromStr for Column {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.parse::<usize>() {
+            Ok(0) => Err(Error::UnknownColumn(s.to_string())),
+            Ok(position) => Ok(Column::Position(position)),
+            Err(_) if s.is_empty() => Err(Error::UnknownColumn(s.to_string())),
+            Err(_) => Ok(Column::Name(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Column {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Column::Name(name) => write!(f, "{}", name),
+            Column::Position(position) => write!(f, "{}", position),
+        }
+    }
+}
+
+/// Splits records into their fields.
+///
+/// CSV records are split using their dialect, and plain lines as CSV with the
+/// default dialect. Other formats have no fields.
+pub struct FieldReader {
+    builder: csv::ReaderBuilder,
+}
+
+impl FieldReader {
+    /// A reader for records of `format`, if they have fields.
+    ///
+    /// If the format sniffs its delimiter, this should be created after the
+    /// first file has been opened, so that the sniffed delimiter is used.
+    pub fn new(format: &RecordFormat) -> Option<Self> {
+        let builder = match format {
+            RecordFormat::Csv(dialect) => dialect.reader_builder(),
+            RecordFormat::Lines => CsvDialect::default().reader_builder(),
+            _ => return None,
+        };
+        Some(FieldReader { builder })
+    }
+
+    /// Split a single record into its fields.
+    pub fn fields(&self, record: &str) -> Result<Vec<String>> {
+        let mut reader = self.builder.from_reader(record.as_bytes());
+        let mut fields = csv::StringRecord::new();
+        reader.read_record(&mut fields)?;
+        Ok(fields.iter().map(str::to_string).collect())
+    }
+}
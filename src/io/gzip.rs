@@ -0,0 +1,230 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::num::NonZeroUsize;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+
+use flate2::write::GzEncoder;
+
+use super::bgzf;
+use super::{FinishWrite, OutputWriter};
+use crate::error::{Error, Result};
+
+/// The amount of uncompressed data in each gzip member.
+const BLOCK_SIZE: usize = 256 * 1024;
+
+/// How compressed blocks are written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GzipFormat {
+    /// Plain gzip members.
+    Gzip,
+    /// BGZF blocks, which record their own size so that a reader can seek
+    /// between them.
+    Bgzf,
+}
+
+impl GzipFormat {
+    /// The amount of uncompressed data in each block.
+    fn block_size(self) -> usize {
+        match self {
+            GzipFormat::Gzip => BLOCK_SIZE,
+            GzipFormat::Bgzf => bgzf::BLOCK_SIZE,
+        }
+    }
+
+    /// Compress a block as a complete gzip member.
+    fn compress(self, level: flate2::Compression, block: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            GzipFormat::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::with_capacity(block.len() / 2 + 64), level);
+                encoder.write_all(block)?;
+                encoder.finish()
+            }
+            GzipFormat::Bgzf => bgzf::compress_block(level, block),
+        }
+    }
+}
+
+/// The highest gzip compression level.
+pub const MAX_COMPRESSION_LEVEL: u32 = 9;
+
+/// A block of uncompressed data, and where to send it once it's compressed.
+struct Job {
+    block: Vec<u8>,
+    result: SyncSender<io::Result<Vec<u8>>>,
+}
+
+/// Compresses outputs as a series of independently compressed blocks, which
+/// are concatenated into a valid multi-member gzip stream in the same way as
+/// `pigz` or `bgzip`.
+///
+/// With more than one thread, blocks are compressed on a pool of threads
+/// shared by every output, so that a single large output can use every core.
+/// The output is the same whatever the number of threads.
+pub struct GzipCompressor {
+    format: GzipFormat,
+    level: flate2::Compression,
+    threads: usize,
+    /// Sends blocks to the pool, if there is one.
+    jobs: Option<Sender<Job>>,
+}
+
+impl GzipCompressor {
+    pub fn new(format: GzipFormat, level: u32, threads: NonZeroUsize) -> Result<Self> {
+        if level > MAX_COMPRESSION_LEVEL {
+            return Err(Error::InvalidCompressionLevel(level));
+        }
+        let level = flate2::Compression::new(level);
+        let threads = threads.get();
+        let jobs = if threads > 1 {
+            let (sender, receiver) = mpsc::channel();
+            let receiver = Arc::new(Mutex::new(receiver));
+            for i in 0..threads {
+                let receiver = Arc::clone(&receiver);
+                // The threads exit once every sender has been dropped.
+                std::thread::Builder::new()
+                    .name(format!("gzip-{}", i))
+                    .spawn(move || compress_jobs(format, level, &receiver))?;
+            }
+            Some(sender)
+        } else {
+            None
+        };
+        Ok(GzipCompressor {
+            format,
+            level,
+            threads,
+            jobs,
+        })
+    }
+
+    pub fn format(&self) -> GzipFormat {
+        self.format
+    }
+
+    /// Wrap `inner` in a writer which compresses everything written to it.
+    pub fn encoder<W: Write + 'static>(&self, inner: W) -> OutputWriter {
+        Box::new(BlockGzEncoder {
+            inner,
+            format: self.format,
+            level: self.level,
+            jobs: self.jobs.clone(),
+            // Keep every thread busy, without buffering much more than that.
+            max_pending: self.threads + 1,
+            block: Vec::with_capacity(self.format.block_size()),
+            pending: VecDeque::new(),
+            members: 0,
+        })
+    }
+}
+
+/// Compress blocks sent to the pool until every sender has been dropped.
+fn compress_jobs(format: GzipFormat, level: flate2::Compression, jobs: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = match jobs.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        // The encoder may have stopped waiting for the result after an error,
+        // so there's no need to report a failure to send it.
+        let _ = job.result.send(format.compress(level, &job.block));
+    }
+}
+
+fn pool_stopped() -> io::Error {
+    io::Error::other("gzip compression thread stopped")
+}
+
+/// Writes a gzip member for each block of data written to it.
+struct BlockGzEncoder<W> {
+    inner: W,
+    format: GzipFormat,
+    level: flate2::Compression,
+    jobs: Option<Sender<Job>>,
+    /// The maximum number of blocks waiting to be compressed.
+    max_pending: usize,
+    /// The block currently being filled.
+    block: Vec<u8>,
+    /// Blocks sent to the pool, oldest first.
+    pending: VecDeque<Receiver<io::Result<Vec<u8>>>>,
+    /// The number of blocks compressed so far.
+    members: u64,
+}
+
+impl<W: Write> BlockGzEncoder<W> {
+    /// Compress the current block, on the pool if there is one.
+    fn submit(&mut self) -> io::Result<()> {
+        let block_size = self.format.block_size();
+        let block = std::mem::replace(&mut self.block, Vec::with_capacity(block_size));
+        self.members += 1;
+        match &self.jobs {
+            None => {
+                let compressed = self.format.compress(self.level, &block)?;
+                self.inner.write_all(&compressed)?;
+            }
+            Some(jobs) => {
+                let (result, receiver) = mpsc::sync_channel(1);
+                jobs.send(Job { block, result })
+                    .map_err(|_| pool_stopped())?;
+                self.pending.push_back(receiver);
+                while self.pending.len() > self.max_pending {
+                    self.write_next()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait for the oldest pending block to be compressed, and write it.
+    fn write_next(&mut self) -> io::Result<()> {
+        if let Some(receiver) = self.pending.pop_front() {
+            let compressed = receiver.recv().map_err(|_| pool_stopped())??;
+            self.inner.write_all(&compressed)?;
+        }
+        Ok(())
+    }
+
+    /// Write every pending block.
+    fn write_pending(&mut self) -> io::Result<()> {
+        while !self.pending.is_empty() {
+            self.write_next()?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for BlockGzEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let block_size = self.format.block_size();
+        let n = buf.len().min(block_size - self.block.len());
+        self.block.extend_from_slice(&buf[..n]);
+        if self.block.len() >= block_size {
+            self.submit()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.block.is_empty() {
+            self.submit()?;
+        }
+        self.write_pending()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> FinishWrite for BlockGzEncoder<W> {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        // Even an empty output needs one member to be valid gzip, but BGZF
+        // always ends with an empty block.
+        let needs_member = self.members == 0 && self.format == GzipFormat::Gzip;
+        if !self.block.is_empty() || needs_member {
+            self.submit()?;
+        }
+        self.write_pending()?;
+        if self.format == GzipFormat::Bgzf {
+            self.inner.write_all(&bgzf::EOF_BLOCK)?;
+        }
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,169 @@
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+
+use log::{debug, info};
+
+use super::checksum::{Checksum, SharedChecksum};
+use super::{open_data, Compression, LineReader, RecordFormat};
+use crate::error::{Error, Result};
+
+/// Expand the user-provided input paths into a list of files to read.
+///
+/// Each input may be:
+///
+/// - `-`, meaning stdin (only allowed as the only input)
+/// - a file
+/// - a directory, in which case every non-hidden file directly inside it is
+///   used, in name order
+/// - a glob pattern such as `part-*.csv.gz`, in which case every matching file
+///   is used, in name order
+pub fn expand_inputs<P: AsRef<Path>>(inputs: &[P]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for input in inputs {
+        let input = input.as_ref();
+        if input.to_str() == Some("-") {
+            if inputs.len() > 1 {
+                return Err(Error::StdinWithOtherInputs);
+            }
+            files.push(input.to_path_buf());
+        } else if input.is_dir() {
+            let mut entries = Vec::new();
+            for entry in read_dir(input)? {
+                let path = entry?.path();
+                let hidden = path
+                    .file_name()
+                    .map(|f| f.to_string_lossy().starts_with('.'))
+                    .unwrap_or(false);
+                if path.is_file() && !hidden {
+                    entries.push(path);
+                }
+            }
+            if entries.is_empty() {
+                return Err(Error::NoInputFiles(input.display().to_string()));
+            }
+            entries.sort();
+            files.append(&mut entries);
+        } else if input.exists() {
+            files.push(input.to_path_buf());
+        } else {
+            let pattern = input.to_string_lossy();
+            let mut entries = glob::glob(&pattern)
+                .map_err(|e| Error::InvalidGlob(e.to_string()))?
+                .filter_map(|entry| entry.ok())
+                .filter(|path| path.is_file())
+                .collect::<Vec<_>>();
+            if entries.is_empty() {
+                return Err(Error::NoInputFiles(pattern.to_string()));
+            }
+            entries.sort();
+            files.append(&mut entries);
+        }
+    }
+    debug!("Expanded inputs to {:?}", files);
+    Ok(files)
+}
+
+/// Reads several files in sequence as if they were a single dataset.
+///
+/// If the files have headers, the header is taken from the first file; the
+/// first line of each subsequent file is checked against it and skipped.
+pub struct MultiReader {
+    paths: Vec<PathBuf>,
+    compression: Compression,
+    format: RecordFormat,
+    has_header: bool,
+    /// The header of the first file, if it has been read.
+    header: Option<String>,
+    /// The size and checksum of each file, updated as it is read.
+    checksums: Vec<SharedChecksum>,
+    /// Index into `paths` of the file currently being read.
+    index: usize,
+    current: Box<dyn LineReader>,
+}
+
+impl MultiReader {
+    pub fn new(
+        paths: Vec<PathBuf>,
+        compression: Compression,
+        mut format: RecordFormat,
+        has_header: bool,
+    ) -> Result<Self> {
+        let first = paths.first().ok_or(Error::EmptyFile)?;
+        let checksums: Vec<SharedChecksum> = paths.iter().map(|_| Default::default()).collect();
+        let current = open_data(first, compression, &mut format, &checksums[0])?;
+        Ok(MultiReader {
+            paths,
+            compression,
+            format,
+            has_header,
+            header: None,
+            checksums,
+            index: 0,
+            current,
+        })
+    }
+
+    /// Move on to the next file, checking and skipping its header.
+    ///
+    /// Returns false if there are no files left.
+    fn next_file(&mut self) -> Result<bool> {
+        self.index += 1;
+        let path = match self.paths.get(self.index) {
+            Some(p) => p,
+            None => return Ok(false),
+        };
+        info!("Reading data from {}", path.display());
+        self.current = open_data(
+            path,
+            self.compression,
+            &mut self.format,
+            &self.checksums[self.index],
+        )?;
+        if let Some(expected) = &self.header {
+            if let Some(header) = self.current.read_line() {
+                let header = header?;
+                if header.trim_end_matches(['\r', '\n']) != expected.trim_end_matches(['\r', '\n'])
+                {
+                    return Err(Error::HeaderMismatch(path.display().to_string()));
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// The size and SHA-256 checksum of every input file, reading the rest of
+    /// any files which weren't read to the end.
+    pub fn checksums(self) -> Result<Vec<(PathBuf, u64, String)>> {
+        // Close the current file before reading the rest of it.
+        drop(self.current);
+        self.paths
+            .into_iter()
+            .zip(&self.checksums)
+            .map(|(path, checksum)| {
+                let (bytes, sha256) = Checksum::finish(checksum, &path)?;
+                Ok((path, bytes, sha256))
+            })
+            .collect()
+    }
+}
+
+impl LineReader for MultiReader {
+    fn read_line(&mut self) -> Option<Result<String>> {
+        loop {
+            match self.current.read_line() {
+                Some(Ok(line)) => {
+                    if self.has_header && self.header.is_none() {
+                        self.header = Some(line.clone());
+                    }
+                    return Some(Ok(line));
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                None => match self.next_file() {
+                    Ok(true) => continue,
+                    Ok(false) => return None,
+                    Err(e) => return Some(Err(e)),
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,490 @@
+use std::fmt;
+use std::io::{BufRead, BufReader, Read};
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::LineReader;
+use crate::error::{Error, Result};
+
+/// Append the next line (including its terminator) to `buf`.
+///
+/// Returns false at the end of the input. A missing newline at the end of the
+/// input is added, so records can be safely written one after another.
+fn append_line(reader: &mut impl BufRead, buf: &mut String) -> Result<bool> {
+    if reader.read_line(buf)? == 0 {
+        return Ok(false);
+    }
+    if !buf.ends_with('\n') {
+        buf.push('\n');
+    }
+    Ok(true)
+}
+
+/// Reads FASTA records: a header line starting with `>` followed by any number
+/// of sequence lines.
+pub struct FastaReader {
+    reader: BufReader<Box<dyn Read>>,
+    /// The header line of the next record, if it has already been read.
+    next_header: Option<String>,
+}
+
+impl FastaReader {
+    pub fn new(reader: BufReader<Box<dyn Read>>) -> Self {
+        FastaReader {
+            reader,
+            next_header: None,
+        }
+    }
+
+    fn read_record(&mut self) -> Result<Option<String>> {
+        let mut record = match self.next_header.take() {
+            Some(header) => header,
+            None => {
+                let mut header = String::new();
+                // Skip any blank lines before the first record.
+                loop {
+                    header.clear();
+                    if !append_line(&mut self.reader, &mut header)? {
+                        return Ok(None);
+                    }
+                    if !header.trim().is_empty() {
+                        break;
+                    }
+                }
+                if !header.starts_with('>') {
+                    return Err(Error::InvalidRecord(format!(
+                        "expected FASTA header starting with '>', found {:?}",
+                        header.trim_end()
+                    )));
+                }
+                header
+            }
+        };
+        loop {
+            let mut line = String::new();
+            if !append_line(&mut self.reader, &mut line)? {
+                break;
+            }
+            if line.starts_with('>') {
+                self.next_header = Some(line);
+                break;
+            }
+            record.push_str(&line);
+        }
+        Ok(Some(record))
+    }
+}
+
+impl LineReader for FastaReader {
+    fn read_line(&mut self) -> Option<Result<String>> {
+        self.read_record().transpose()
+    }
+}
+
+/// Reads FASTQ records, each made up of four lines: a header starting with
+/// `@`, the sequence, a separator starting with `+` and the quality scores.
+pub struct FastqReader {
+    reader: BufReader<Box<dyn Read>>,
+}
+
+impl FastqReader {
+    pub fn new(reader: BufReader<Box<dyn Read>>) -> Self {
+        FastqReader { reader }
+    }
+
+    fn read_record(&mut self) -> Result<Option<String>> {
+        let mut record = String::new();
+        for line in 0..4 {
+            let start = record.len();
+            if !append_line(&mut self.reader, &mut record)? {
+                if line == 0 {
+                    return Ok(None);
+                }
+                return Err(Error::InvalidRecord(format!(
+                    "incomplete FASTQ record {:?}",
+                    record.trim_end()
+                )));
+            }
+            let expected = match line {
+                0 => Some('@'),
+                2 => Some('+'),
+                _ => None,
+            };
+            if let Some(expected) = expected {
+                if !record[start..].starts_with(expected) {
+                    return Err(Error::InvalidRecord(format!(
+                        "expected FASTQ line starting with '{}', found {:?}",
+                        expected,
+                        record[start..].trim_end()
+                    )));
+                }
+            }
+        }
+        Ok(Some(record))
+    }
+}
+
+impl LineReader for FastqReader {
+    fn read_line(&mut self) -> Option<Result<String>> {
+        self.read_record().transpose()
+    }
+}
+
+/// Reads records made up of a fixed number of lines.
+pub struct MultiLineReader {
+    reader: BufReader<Box<dyn Read>>,
+    lines_per_record: usize,
+}
+
+impl MultiLineReader {
+    pub fn new(reader: BufReader<Box<dyn Read>>, lines_per_record: usize) -> Self {
+        MultiLineReader {
+            reader,
+            lines_per_record,
+        }
+    }
+
+    fn read_record(&mut self) -> Result<Option<String>> {
+        let mut record = String::new();
+        for line in 0..self.lines_per_record {
+            if !append_line(&mut self.reader, &mut record)? {
+                if line == 0 {
+                    return Ok(None);
+                }
+                return Err(Error::InvalidRecord(format!(
+                    "incomplete record of {} lines, expected {}",
+                    line, self.lines_per_record
+                )));
+            }
+        }
+        Ok(Some(record))
+    }
+}
+
+impl LineReader for MultiLineReader {
+    fn read_line(&mut self) -> Option<Result<String>> {
+        self.read_record().transpose()
+    }
+}
+
+/// Separates records in plain text input.
+#[derive(Clone, Debug)]
+pub enum RecordSeparator {
+    /// A literal byte string, such as `\0`.
+    Literal(Vec<u8>),
+    /// One or more blank lines, as used by paragraph or CoNLL style files.
+    BlankLine,
+    /// Any match of a regular expression.
+    Regex(regex::bytes::Regex),
+}
+
+impl RecordSeparator {
+    /// The longest the separator can be, or `None` if it's unbounded.
+    fn max_len(&self) -> Option<usize> {
+        match self {
+            RecordSeparator::Literal(literal) => Some(literal.len()),
+            RecordSeparator::BlankLine => None,
+            RecordSeparator::Regex(regex) => regex_syntax::ParserBuilder::new()
+                .utf8(false)
+                .build()
+                .parse(regex.as_str())
+                .ok()
+                .and_then(|hir| hir.properties().maximum_len()),
+        }
+    }
+
+    fn regex(&self) -> regex::bytes::Regex {
+        let pattern = match self {
+            RecordSeparator::Literal(literal) => literal
+                .iter()
+                .map(|b| format!("\\x{b:02x}"))
+                .collect::<String>(),
+            RecordSeparator::BlankLine => r"\r?\n(?:[ \t]*\r?\n)+".to_string(),
+            RecordSeparator::Regex(regex) => return regex.clone(),
+        };
+        regex::bytes::Regex::new(&format!("(?-u){pattern}")).expect("valid separator regex")
+    }
+}
+
+impl FromStr for RecordSeparator {
+    type Err = Error;
+
+    /// Parse a separator from a string specification: `blank` for blank
+    /// lines, `regex:<pattern>` for a regular expression, or otherwise a
+    /// literal string which may contain the escapes `\n`, `\r`, `\t`, `\0`,
+    /// `\\` and `\xNN`.
+    fn from_str(spec: &str) -> Result<Self> {
+        if spec == "blank" {
+            return Ok(RecordSeparator::BlankLine);
+        }
+        if let Some(pattern) = spec.strip_prefix("regex:") {
+            let regex = regex::bytes::Regex::new(pattern)
+                .map_err(|e| Error::InvalidRecordSeparator(e.to_string()))?;
+            if regex.is_match(b"") {
+                return Err(Error::InvalidRecordSeparator(format!(
+                    "{pattern} matches an empty string"
+                )));
+            }
+            return Ok(RecordSeparator::Regex(regex));
+        }
+        let invalid = || Error::InvalidRecordSeparator(spec.to_string());
+        let mut literal = Vec::with_capacity(spec.len());
+        let mut bytes = spec.bytes();
+        while let Some(b) = bytes.next() {
+            if b != b'\\' {
+                literal.push(b);
+                continue;
+            }
+            match bytes.next().ok_or_else(invalid)? {
+                b'n' => literal.push(b'\n'),
+                b'r' => literal.push(b'\r'),
+                b't' => literal.push(b'\t'),
+                b'0' => literal.push(b'\0'),
+                b'\\' => literal.push(b'\\'),
+                b'x' => {
+                    let hex = [
+                        bytes.next().ok_or_else(invalid)?,
+                        bytes.next().ok_or_else(invalid)?,
+                    ];
+                    let hex = std::str::from_utf8(&hex).map_err(|_| invalid())?;
+                    literal.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        if literal.is_empty() {
+            return Err(invalid());
+        }
+        Ok(RecordSeparator::Literal(literal))
+    }
+}
+
+impl fmt::Display for RecordSeparator {
+    /// Format the separator as a specification which parses back to it.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordSeparator::BlankLine => f.write_str("blank"),
+            RecordSeparator::Regex(regex) => write!(f, "regex:{}", regex.as_str()),
+            RecordSeparator::Literal(literal) => {
+                // Escape the first byte of literals which would otherwise be
+                // read back as another kind of separator.
+                let ambiguous = literal == b"blank" || literal.starts_with(b"regex:");
+                for (i, b) in literal.iter().enumerate() {
+                    match b {
+                        _ if i == 0 && ambiguous => write!(f, "\\x{b:02x}")?,
+                        b'\n' => f.write_str("\\n")?,
+                        b'\r' => f.write_str("\\r")?,
+                        b'\t' => f.write_str("\\t")?,
+                        b'\0' => f.write_str("\\0")?,
+                        b'\\' => f.write_str("\\\\")?,
+                        b' '..=b'~' => write!(f, "{}", *b as char)?,
+                        _ => write!(f, "\\x{b:02x}")?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Serialize for RecordSeparator {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for RecordSeparator {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Reads plain text records divided by a `RecordSeparator`.
+///
+/// Each record is returned along with the separator that followed it, so the
+/// original bytes are written back out. If the final record has no
+/// separator, one is added.
+pub struct SeparatedReader {
+    reader: Box<dyn Read>,
+    separator: RecordSeparator,
+    regex: regex::bytes::Regex,
+    /// Finds literal separators, which is quicker than the regex.
+    finder: Option<memchr::memmem::Finder<'static>>,
+    max_separator_len: Option<usize>,
+    buf: Vec<u8>,
+    /// Offset in `buf` of the start of the next record.
+    start: usize,
+    /// Offset in `buf` before which no separator can start, so that long
+    /// records aren't searched again after each read.
+    scanned: usize,
+    eof: bool,
+}
+
+impl SeparatedReader {
+    pub fn new(reader: Box<dyn Read>, separator: RecordSeparator) -> Self {
+        let finder = match &separator {
+            RecordSeparator::Literal(literal) => {
+                Some(memchr::memmem::Finder::new(literal).into_owned())
+            }
+            _ => None,
+        };
+        SeparatedReader {
+            reader,
+            regex: separator.regex(),
+            finder,
+            max_separator_len: separator.max_len(),
+            separator,
+            buf: Vec::with_capacity(1024 * 1024),
+            start: 0,
+            scanned: 0,
+            eof: false,
+        }
+    }
+
+    /// Read more data into the buffer, discarding any records already
+    /// returned.
+    fn fill_buf(&mut self) -> Result<()> {
+        self.buf.drain(..self.start);
+        self.scanned -= self.start;
+        self.start = 0;
+        let len = self.buf.len();
+        self.buf.resize(len + 64 * 1024, 0);
+        let read = self.reader.read(&mut self.buf[len..]);
+        let read = match read {
+            Ok(read) => read,
+            Err(e) => {
+                self.buf.truncate(len);
+                return Err(e.into());
+            }
+        };
+        self.buf.truncate(len + read);
+        self.eof = read == 0;
+        Ok(())
+    }
+
+    /// Find the next separator at or after `from`, returning its start and end
+    /// offsets in `buf`.
+    fn find(&self, from: usize) -> Option<(usize, usize)> {
+        let record = &self.buf[self.start..];
+        let from = from - self.start;
+        let found = match &self.finder {
+            Some(finder) => finder
+                .find(&record[from..])
+                .map(|i| (from + i, from + i + finder.needle().len())),
+            None => self
+                .regex
+                .find_at(record, from)
+                .map(|m| (m.start(), m.end())),
+        };
+        found.map(|(start, end)| (self.start + start, self.start + end))
+    }
+
+    /// The offset from which to search again once more data has been read,
+    /// given that the search from `from` found no separator which is known to
+    /// be complete. Only the end of the buffer, where a separator may have
+    /// been cut off, is searched again.
+    fn resume_from(&self, from: usize) -> usize {
+        let len = self.buf.len();
+        match (&self.separator, self.max_separator_len) {
+            (RecordSeparator::BlankLine, _) => {
+                // Blank lines can run on indefinitely, but only through
+                // whitespace.
+                let whitespace = self.buf[from..]
+                    .iter()
+                    .rev()
+                    .take_while(|b| b" \t\r\n".contains(b))
+                    .count();
+                len - whitespace
+            }
+            (_, Some(max_len)) => len.saturating_sub(max_len.saturating_sub(1)).max(from),
+            (_, None) => from,
+        }
+    }
+
+    fn read_record(&mut self) -> Result<Option<Vec<u8>>> {
+        loop {
+            let from = self.scanned.max(self.start);
+            let found = self.find(from);
+            match found {
+                // A separator at the very end of the buffer might continue
+                // into the next read (e.g. more blank lines), so only accept
+                // it once we know that it can't.
+                Some((sep_start, sep_end))
+                    if sep_end > sep_start && (sep_end < self.buf.len() || self.eof) =>
+                {
+                    let record = self.buf[self.start..sep_end].to_vec();
+                    let empty = sep_start == self.start;
+                    self.start = sep_end;
+                    if empty && matches!(self.separator, RecordSeparator::BlankLine) {
+                        continue;
+                    }
+                    return Ok(Some(record));
+                }
+                _ if self.eof => {
+                    if self.start == self.buf.len() {
+                        return Ok(None);
+                    }
+                    let mut record = self.buf[self.start..].to_vec();
+                    self.start = self.buf.len();
+                    match &self.separator {
+                        RecordSeparator::Literal(literal) => record.extend_from_slice(literal),
+                        RecordSeparator::BlankLine => {
+                            while record.last().is_some_and(|b| *b == b'\n' || *b == b'\r') {
+                                record.pop();
+                            }
+                            if record.iter().all(|b| b.is_ascii_whitespace()) {
+                                return Ok(None);
+                            }
+                            record.extend_from_slice(b"\n\n");
+                        }
+                        // A match of the regex can't be made up, so end the
+                        // record with a newline so it doesn't run into the
+                        // next one when written out.
+                        RecordSeparator::Regex(_) => {
+                            if record.last() != Some(&b'\n') {
+                                record.push(b'\n');
+                            }
+                        }
+                    }
+                    return Ok(Some(record));
+                }
+                _ => {
+                    let resume = self.resume_from(from);
+                    self.scanned = found.map_or(resume, |(sep_start, _)| sep_start.min(resume));
+                    // A regex with no maximum length has to search the whole
+                    // record again, so only do so once it's doubled in size,
+                    // to keep the time linear in its length.
+                    let unbounded = matches!(self.separator, RecordSeparator::Regex(_))
+                        && self.max_separator_len.is_none();
+                    let target = match unbounded {
+                        true => 2 * (self.buf.len() - self.start),
+                        false => 0,
+                    };
+                    self.fill_buf()?;
+                    while !self.eof && self.buf.len() < target {
+                        self.fill_buf()?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl LineReader for SeparatedReader {
+    fn read_line(&mut self) -> Option<Result<String>> {
+        self.read_record()
+            .and_then(|record| {
+                record
+                    .map(|r| {
+                        String::from_utf8(r).map_err(|e| {
+                            std::io::Error::new(std::io::ErrorKind::InvalidData, e).into()
+                        })
+                    })
+                    .transpose()
+            })
+            .transpose()
+    }
+}
@@ -0,0 +1,670 @@
+use std::fmt;
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use clap::CommandFactory;
+use serde::de::{MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use toml_edit::{DocumentMut, Item, Table, Value};
+
+use crate::cli::{self, parse_byte, parse_size, RecordOptions};
+use crate::error::{Error, Result};
+use crate::io::{RecordSeparator, MAX_COMPRESSION_LEVEL};
+use crate::split::{OutputTemplate, ProportionSplit, RowSplit, RunDir};
+
+/// Options which can't be given together, as on the command line.
+const CONFLICTS: &[(&str, &str)] = &[
+    ("rows", "prop"),
+    ("csv", "fasta"),
+    ("csv", "fastq"),
+    ("csv", "lines-per-record"),
+    ("csv", "record-separator"),
+    ("fasta", "fastq"),
+    ("fasta", "lines-per-record"),
+    ("fasta", "record-separator"),
+    ("fastq", "lines-per-record"),
+    ("fastq", "record-separator"),
+    ("lines-per-record", "record-separator"),
+    ("fasta", "delimiter"),
+    ("fasta", "quote"),
+    ("fasta", "escape"),
+    ("fasta", "no-double-quote"),
+    ("fasta", "comment"),
+    ("fasta", "terminator"),
+    ("fasta", "sniff"),
+    ("fastq", "delimiter"),
+    ("fastq", "quote"),
+    ("fastq", "escape"),
+    ("fastq", "no-double-quote"),
+    ("fastq", "comment"),
+    ("fastq", "terminator"),
+    ("fastq", "sniff"),
+    ("lines-per-record", "delimiter"),
+    ("lines-per-record", "quote"),
+    ("lines-per-record", "escape"),
+    ("lines-per-record", "no-double-quote"),
+    ("lines-per-record", "comment"),
+    ("lines-per-record", "terminator"),
+    ("lines-per-record", "sniff"),
+    ("record-separator", "delimiter"),
+    ("record-separator", "quote"),
+    ("record-separator", "escape"),
+    ("record-separator", "no-double-quote"),
+    ("record-separator", "comment"),
+    ("record-separator", "terminator"),
+    ("record-separator", "sniff"),
+    ("sniff", "delimiter"),
+    ("shards", "chunk-size"),
+    ("shards", "chunk-bytes"),
+    ("shuffle", "chunk-bytes-compressed"),
+];
+
+/// Options which need one of some others, as on the command line.
+const REQUIRES: &[(&str, &[&str])] = &[
+    ("chunk-bytes-compressed", &["chunk-bytes"]),
+    ("compression-level", &["compressed-output", "bgzf"]),
+    ("compression-threads", &["compressed-output", "bgzf"]),
+    ("shuffle-memory", &["shuffle"]),
+    ("temp-dir", &["shuffle"]),
+];
+
+/// Options whose values are integers. Overrides of every other option are
+/// read as strings, as they would be on the command line.
+const INTEGERS: &[&str] = &[
+    "lines-per-record",
+    "chunk-size",
+    "shards",
+    "total-rows",
+    "seed",
+    "compression-level",
+    "compression-threads",
+];
+
+/// Options whose values are paths, which are relative to the job file.
+const PATHS: &[&str] = &["input", "output-prefix", "output-dir", "temp-dir"];
+
+/// A split, described by the same options as the `split` command.
+///
+/// In a job file, keys are the long names of the options, e.g.
+/// `chunk-size = 1000`, with flags given as booleans. `input` may be a single
+/// path or a list, and `rows` or `prop` a table such as
+/// `{ train = 0.8, test = 0.2 }` or a list of specifications.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Job {
+    /// The name of the job. Jobs without one are named after their position
+    /// in the file, counting from 1.
+    pub name: Option<String>,
+    #[serde(deserialize_with = "deserialize_paths")]
+    pub input: Vec<PathBuf>,
+    #[serde(deserialize_with = "deserialize_splits")]
+    pub rows: Vec<RowSplit>,
+    #[serde(deserialize_with = "deserialize_splits")]
+    pub prop: Vec<ProportionSplit>,
+    pub csv: bool,
+    pub fasta: bool,
+    pub fastq: bool,
+    pub lines_per_record: Option<NonZeroUsize>,
+    pub record_separator: Option<RecordSeparator>,
+    #[serde(deserialize_with = "deserialize_byte")]
+    pub delimiter: Option<u8>,
+    #[serde(deserialize_with = "deserialize_byte")]
+    pub quote: Option<u8>,
+    #[serde(deserialize_with = "deserialize_byte")]
+    pub escape: Option<u8>,
+    pub no_double_quote: bool,
+    #[serde(deserialize_with = "deserialize_byte")]
+    pub comment: Option<u8>,
+    #[serde(deserialize_with = "deserialize_byte")]
+    pub terminator: Option<u8>,
+    pub sniff: bool,
+    pub decompress_input: bool,
+    pub no_header: bool,
+    pub chunk_size: Option<NonZeroU64>,
+    #[serde(deserialize_with = "deserialize_size")]
+    pub chunk_bytes: Option<u64>,
+    pub chunk_bytes_compressed: bool,
+    pub shards: Option<NonZeroU64>,
+    pub contiguous: bool,
+    pub total_rows: Option<u64>,
+    pub seed: Option<u64>,
+    pub output_prefix: Option<PathBuf>,
+    pub output_dir: Option<PathBuf>,
+    #[serde(deserialize_with = "deserialize_from_str")]
+    pub output_template: Option<OutputTemplate>,
+    pub output_extension: Option<String>,
+    #[serde(rename = "compressed-output")]
+    pub compress_output: bool,
+    pub bgzf: bool,
+    #[serde(deserialize_with = "deserialize_compression_level")]
+    pub compression_level: Option<u32>,
+    pub compression_threads: Option<NonZeroUsize>,
+    pub shuffle: bool,
+    #[serde(deserialize_with = "deserialize_size")]
+    pub shuffle_memory: Option<u64>,
+    pub temp_dir: Option<PathBuf>,
+    pub fsync: bool,
+    pub force: bool,
+    pub run_dir: Option<RunDir>,
+}
+
+impl Job {
+    /// The options which decide where a job's outputs are written, so that
+    /// jobs which would write to the same files can be found.
+    fn outputs(&self) -> String {
+        format!(
+            "{:?}",
+            (
+                &self.input,
+                &self.output_prefix,
+                &self.output_dir,
+                &self.output_template,
+                &self.output_extension,
+                &self.run_dir,
+                self.compress_output || self.bgzf,
+            )
+        )
+    }
+
+    /// The options used to read the input, as they'd be given on the command
+    /// line.
+    pub fn record_options(&self) -> RecordOptions {
+        RecordOptions {
+            csv: self.csv,
+            fasta: self.fasta,
+            fastq: self.fastq,
+            lines_per_record: self.lines_per_record,
+            record_separator: self.record_separator.clone(),
+            delimiter: self.delimiter,
+            quote: self.quote,
+            escape: self.escape,
+            no_double_quote: self.no_double_quote,
+            comment: self.comment,
+            terminator: self.terminator,
+            sniff: self.sniff,
+            decompress_input: self.decompress_input,
+        }
+    }
+}
+
+impl From<cli::Split> for Job {
+    fn from(x: cli::Split) -> Self {
+        let records = x.records;
+        Job {
+            name: None,
+            input: x.input,
+            rows: x.rows,
+            prop: x.prop,
+            csv: records.csv,
+            fasta: records.fasta,
+            fastq: records.fastq,
+            lines_per_record: records.lines_per_record,
+            record_separator: records.record_separator,
+            delimiter: records.delimiter,
+            quote: records.quote,
+            escape: records.escape,
+            no_double_quote: records.no_double_quote,
+            comment: records.comment,
+            terminator: records.terminator,
+            sniff: records.sniff,
+            decompress_input: records.decompress_input,
+            no_header: x.no_header,
+            chunk_size: x.chunk_size,
+            chunk_bytes: x.chunk_bytes,
+            chunk_bytes_compressed: x.chunk_bytes_compressed,
+            shards: x.shards,
+            contiguous: x.contiguous,
+            total_rows: x.total_rows,
+            seed: x.seed,
+            output_prefix: x.output_prefix,
+            output_dir: x.output_dir,
+            output_template: x.output_template,
+            output_extension: x.output_extension,
+            compress_output: x.compress_output,
+            bgzf: x.bgzf,
+            compression_level: x.compression_level,
+            compression_threads: x.compression_threads,
+            shuffle: x.shuffle,
+            shuffle_memory: x.shuffle_memory,
+            temp_dir: x.temp_dir,
+            fsync: x.fsync,
+            force: x.force,
+            run_dir: x.run_dir,
+        }
+    }
+}
+
+/// The jobs in a job file, as run by `ttv run`.
+///
+/// Jobs are given as an array of `[[job]]` tables. Keys outside of them are
+/// defaults for every job, so a file without any `[[job]]` tables is a single
+/// job.
+#[derive(Debug)]
+pub struct JobFile {
+    pub jobs: Vec<Job>,
+}
+
+impl JobFile {
+    /// Read the job file at `path`, applying `overrides` to every job.
+    ///
+    /// Overrides are options of the `split` command, such as `--seed 42`, or
+    /// `--shuffle false` to turn off a flag set in the file. They replace the
+    /// values in the file.
+    ///
+    /// Relative paths in the file are relative to the directory containing it,
+    /// while those in overrides are relative to the working directory.
+    pub fn read<P: AsRef<Path>>(path: P, overrides: &[String]) -> Result<Self> {
+        let path = path.as_ref();
+        let invalid = |message: String| Error::InvalidJobFile(path.display().to_string(), message);
+        let mut document: DocumentMut = std::fs::read_to_string(path)?
+            .parse()
+            .map_err(|e: toml_edit::TomlError| invalid(e.to_string()))?;
+        let overrides = parse_overrides(overrides)?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        let mut tables: Vec<Table> = match document.remove("job") {
+            None => vec![Table::new()],
+            Some(item) => item
+                .into_array_of_tables()
+                .map_err(|_| invalid("job must be an array of tables, i.e. [[job]]".to_string()))?
+                .into_iter()
+                .collect(),
+        };
+        if tables.len() > 1 && document.contains_key("name") {
+            return Err(invalid(
+                "name can't be shared by every job, give it in each [[job]] table".to_string(),
+            ));
+        }
+        resolve_paths(document.as_table_mut(), base);
+        for table in &mut tables {
+            resolve_paths(table, base);
+        }
+
+        let mut jobs = Vec::with_capacity(tables.len());
+        for (i, table) in tables.iter().enumerate() {
+            let position = (i + 1).to_string();
+            let mut merged = Table::new();
+            for layer in [document.as_table(), table, &overrides] {
+                // Splits are given either by rows or by proportion, so giving
+                // one replaces the other.
+                if layer.contains_key("rows") || layer.contains_key("prop") {
+                    merged.remove("rows");
+                    merged.remove("prop");
+                }
+                for (key, item) in layer.iter() {
+                    merged.insert(key, item.clone());
+                }
+            }
+            let name = match merged.get("name").and_then(Item::as_str) {
+                Some(name) => name.to_string(),
+                None => position,
+            };
+            check_options(&merged).map_err(|message| invalid(format!("job {name}: {message}")))?;
+            let mut job: Job = toml_edit::de::from_document(DocumentMut::from(merged))
+                .map_err(|e| invalid(format!("job {name}: {}", e.message())))?;
+            job.name = Some(name);
+            jobs.push(job);
+        }
+
+        // Keys outside the [[job]] tables apply to every job, so jobs can end
+        // up writing to the same files. Catch that before any job runs.
+        for (i, job) in jobs.iter().enumerate() {
+            if let Some(other) = jobs[..i].iter().find(|o| o.outputs() == job.outputs()) {
+                return Err(invalid(format!(
+                    "jobs {} and {} write to the same outputs, give them different \
+                     output-dir or output-prefix values",
+                    other.name.as_deref().unwrap_or_default(),
+                    job.name.as_deref().unwrap_or_default(),
+                )));
+            }
+        }
+        Ok(JobFile { jobs })
+    }
+
+    /// The jobs with the given names, in the order they're in the file, or
+    /// every job if no names are given.
+    pub fn select(self, names: &[String]) -> Result<Vec<Job>> {
+        let is_named = |job: &Job, name: &String| job.name.as_ref() == Some(name);
+        if let Some(name) = names
+            .iter()
+            .find(|name| !self.jobs.iter().any(|job| is_named(job, name)))
+        {
+            return Err(Error::UnknownJob(name.clone()));
+        }
+        Ok(self
+            .jobs
+            .into_iter()
+            .filter(|job| names.is_empty() || names.iter().any(|name| is_named(job, name)))
+            .collect())
+    }
+}
+
+/// Check the options of a job for the same mistakes the command line rejects:
+/// missing inputs or splits, options which conflict, and options which need
+/// another. Flags set to false, and empty lists, count as not given.
+fn check_options(table: &Table) -> std::result::Result<(), String> {
+    let given = |key: &str| {
+        table.get(key).is_some_and(|item| {
+            item.as_bool() != Some(false) && item.as_array().is_none_or(|a| !a.is_empty())
+        })
+    };
+    if !given("input") {
+        return Err("input is required".to_string());
+    }
+    if !given("rows") && !given("prop") {
+        return Err("one of rows or prop is required".to_string());
+    }
+    for (a, b) in CONFLICTS {
+        if given(a) && given(b) {
+            return Err(format!("{a} can't be used with {b}"));
+        }
+    }
+    for (a, needs) in REQUIRES {
+        if given(a) && !needs.iter().any(|n| given(n)) {
+            return Err(format!("{a} requires {}", needs.join(" or ")));
+        }
+    }
+    Ok(())
+}
+
+/// Make the relative paths in a table of options relative to `base`, the
+/// directory containing the job file, rather than the working directory.
+fn resolve_paths(table: &mut Table, base: &Path) {
+    for key in PATHS {
+        let values: Vec<&mut Value> = match table.get_mut(key) {
+            Some(Item::Value(Value::Array(array))) => array.iter_mut().collect(),
+            Some(Item::Value(value)) => vec![value],
+            _ => continue,
+        };
+        for value in values {
+            let resolved = match value.as_str() {
+                Some(path) if path != "-" && Path::new(path).is_relative() => base.join(path),
+                _ => continue,
+            };
+            if let Some(resolved) = resolved.to_str() {
+                *value = Value::from(resolved);
+            }
+        }
+    }
+}
+
+/// Parse command line overrides, such as `--seed 42 --force` or `-s 42`, into
+/// a table of options, keyed by their long names.
+///
+/// Options are looked up in the definition of the `split` command, so the same
+/// long and short names are accepted. Flags may be followed by `true` or
+/// `false`, and options taking several values, such as `--rows`, take a comma
+/// separated list as they do on the command line.
+fn parse_overrides(args: &[String]) -> Result<Table> {
+    let command = cli::Split::command();
+    let mut overrides = Table::new();
+    let mut args = args.iter().peekable();
+    while let Some(arg) = args.next() {
+        let invalid = || Error::InvalidOverride(arg.clone());
+        let (option, inline) = match arg.strip_prefix("--") {
+            Some(long) => match long.split_once('=') {
+                Some((name, value)) => (find_long(&command, name), Some(value)),
+                None => (find_long(&command, long), None),
+            },
+            None => {
+                let mut chars = arg.strip_prefix('-').ok_or_else(invalid)?.chars();
+                let short = chars.next().ok_or_else(invalid)?;
+                let rest = chars.as_str();
+                let inline = (!rest.is_empty()).then(|| rest.strip_prefix('=').unwrap_or(rest));
+                let option = command
+                    .get_arguments()
+                    .find(|a| a.get_short() == Some(short));
+                (option, inline)
+            }
+        };
+        let option = option.ok_or_else(invalid)?;
+        let key = option.get_long().ok_or_else(invalid)?;
+
+        let value = if !option.is_takes_value_set() {
+            let value = inline.or_else(|| {
+                args.next_if(|a| *a == "true" || *a == "false")
+                    .map(String::as_str)
+            });
+            match value {
+                None | Some("true") => Value::from(true),
+                Some("false") => Value::from(false),
+                Some(_) => return Err(invalid()),
+            }
+        } else if let Some(value) = inline {
+            parse_value(key, value)
+        } else {
+            parse_value(key, args.next().ok_or_else(invalid)?)
+        };
+        overrides.insert(key, Item::Value(value));
+    }
+    Ok(overrides)
+}
+
+/// The option of the `split` command with the long name `name`.
+fn find_long<'a>(
+    command: &'a clap::Command<'static>,
+    name: &str,
+) -> Option<&'a clap::Arg<'static>> {
+    command.get_arguments().find(|a| a.get_long() == Some(name))
+}
+
+/// Read the value of an override as an integer if the option takes one, or
+/// otherwise as a string.
+fn parse_value(key: &str, value: &str) -> Value {
+    match value.parse::<i64>() {
+        Ok(n) if INTEGERS.contains(&key) => Value::from(n),
+        _ => Value::from(value),
+    }
+}
+
+/// Deserialize one path, or a list of them.
+fn deserialize_paths<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<PathBuf>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Paths {
+        One(PathBuf),
+        Many(Vec<PathBuf>),
+    }
+    Ok(match Paths::deserialize(deserializer)? {
+        Paths::One(path) => vec![path],
+        Paths::Many(paths) => paths,
+    })
+}
+
+/// Deserialize splits from a table of names to amounts, a list of
+/// specifications such as `"train=0.8"`, or a comma separated string of them.
+fn deserialize_splits<'de, D, T>(deserializer: D) -> std::result::Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err = Error>,
+{
+    struct SplitsVisitor<T>(std::marker::PhantomData<T>);
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Amount {
+        Rows(u64),
+        Proportion(f64),
+    }
+
+    impl fmt::Display for Amount {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Amount::Rows(rows) => write!(f, "{rows}"),
+                Amount::Proportion(proportion) => write!(f, "{proportion}"),
+            }
+        }
+    }
+
+    fn parse<T: FromStr<Err = Error>, E: serde::de::Error>(
+        spec: &str,
+    ) -> std::result::Result<T, E> {
+        spec.parse().map_err(E::custom)
+    }
+
+    impl<'de, T: FromStr<Err = Error>> Visitor<'de> for SplitsVisitor<T> {
+        type Value = Vec<T>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a table of splits, such as { train = 0.8, test = 0.2 }")
+        }
+
+        fn visit_str<E: serde::de::Error>(self, s: &str) -> std::result::Result<Vec<T>, E> {
+            s.split(',').map(parse).collect()
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<Vec<T>, A::Error> {
+            let mut splits = Vec::new();
+            while let Some(spec) = seq.next_element::<String>()? {
+                splits.push(parse(&spec)?);
+            }
+            Ok(splits)
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> std::result::Result<Vec<T>, A::Error> {
+            let mut splits = Vec::new();
+            while let Some((name, amount)) = map.next_entry::<String, Amount>()? {
+                splits.push(parse(&format!("{name}={amount}"))?);
+            }
+            Ok(splits)
+        }
+    }
+
+    deserializer.deserialize_any(SplitsVisitor(std::marker::PhantomData))
+}
+
+/// Deserialize a byte from a character, or a name such as `tab`, as accepted
+/// on the command line.
+fn deserialize_byte<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<u8>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    parse_byte(&s).map(Some).map_err(serde::de::Error::custom)
+}
+
+/// Deserialize a size in bytes from a number, or a string such as `"256M"`.
+fn deserialize_size<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+    let size = match Size::deserialize(deserializer)? {
+        Size::Bytes(bytes) => bytes.to_string(),
+        Size::Text(text) => text,
+    };
+    parse_size(&size)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_compression_level<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<u32>, D::Error> {
+    let level = u32::deserialize(deserializer)?;
+    if level > MAX_COMPRESSION_LEVEL {
+        return Err(serde::de::Error::custom(Error::InvalidCompressionLevel(
+            level,
+        )));
+    }
+    Ok(Some(level))
+}
+
+fn deserialize_from_str<'de, D, T>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err = Error>,
+{
+    let s = String::deserialize(deserializer)?;
+    s.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overrides(args: &[&str]) -> Result<Table> {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        parse_overrides(&args)
+    }
+
+    /// The overrides parsed from `args`, as they would be written in a job
+    /// file.
+    fn parsed(args: &[&str]) -> Vec<(String, String)> {
+        overrides(args)
+            .unwrap()
+            .iter()
+            .map(|(key, item)| (key.to_string(), item.to_string().trim().to_string()))
+            .collect()
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn invalid(args: &[&str]) -> String {
+        match overrides(args) {
+            Err(Error::InvalidOverride(arg)) => arg,
+            other => panic!("expected an invalid override, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn overrides_accept_long_and_short_names() {
+        let expected = pairs(&[("seed", "42")]);
+        assert_eq!(parsed(&["--seed", "42"]), expected);
+        assert_eq!(parsed(&["--seed=42"]), expected);
+        assert_eq!(parsed(&["-s", "42"]), expected);
+        assert_eq!(parsed(&["-s42"]), expected);
+        assert_eq!(parsed(&["-s=42"]), expected);
+    }
+
+    #[test]
+    fn overrides_read_integers_only_for_integer_options() {
+        assert_eq!(
+            parsed(&["--chunk-size", "100", "--output-prefix", "42"]),
+            pairs(&[("chunk-size", "100"), ("output-prefix", "\"42\"")])
+        );
+        assert_eq!(
+            parsed(&["--chunk-bytes", "256M"]),
+            pairs(&[("chunk-bytes", "\"256M\"")])
+        );
+    }
+
+    #[test]
+    fn overrides_read_flags() {
+        assert_eq!(
+            parsed(&["--force", "--no-header", "false", "--fsync=true"]),
+            pairs(&[("force", "true"), ("no-header", "false"), ("fsync", "true")])
+        );
+        assert_eq!(invalid(&["--force", "maybe"]), "maybe");
+        assert_eq!(invalid(&["--force=maybe"]), "--force=maybe");
+    }
+
+    #[test]
+    fn overrides_read_lists() {
+        assert_eq!(
+            parsed(&["--rows", "train=8,test=2", "--seed", "1"]),
+            pairs(&[("rows", "\"train=8,test=2\""), ("seed", "1")])
+        );
+        // As on the command line, a list is one value.
+        assert_eq!(invalid(&["--rows", "train=8", "test=2"]), "test=2");
+    }
+
+    #[test]
+    fn overrides_reject_unknown_and_incomplete_options() {
+        assert_eq!(invalid(&["--nope", "1"]), "--nope");
+        assert_eq!(invalid(&["-Z"]), "-Z");
+        assert_eq!(invalid(&["seed", "42"]), "seed");
+        assert_eq!(invalid(&["-"]), "-");
+        assert_eq!(invalid(&["--seed"]), "--seed");
+    }
+}
@@ -0,0 +1,426 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use log::info;
+use sha2::{Digest, Sha256};
+
+use crate::error::{Error, Result};
+use crate::io::{open_data, Column, FieldReader, SharedChecksum, TempDir};
+use crate::split::Manifest;
+
+/// The default amount of memory to hold key hashes in.
+pub const DEFAULT_LEAKAGE_MEMORY: u64 = 512 * 1024 * 1024;
+
+/// The default number of leaked keys to print.
+pub const DEFAULT_LEAKAGE_EXAMPLES: usize = 5;
+
+/// The size of each key's hash and split on disk.
+const ENTRY_BYTES: usize = 20;
+
+/// The most partitions to write at once, to stay well within limits on open
+/// files.
+const MAX_PARTITIONS: u64 = 1024;
+
+pub struct LeakageCheckerBuilder {
+    /// A manifest, or a directory containing one
+    path: PathBuf,
+    /// The columns which identify an entity, or empty to compare whole rows
+    keys: Vec<Column>,
+    /// The number of bytes of key hashes to hold in memory
+    memory: u64,
+    /// The directory to write temporary files to
+    temp_dir: Option<PathBuf>,
+    /// The number of leaked keys to print
+    examples: usize,
+}
+
+impl LeakageCheckerBuilder {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        LeakageCheckerBuilder {
+            path: path.as_ref().to_path_buf(),
+            keys: Vec::new(),
+            memory: DEFAULT_LEAKAGE_MEMORY,
+            temp_dir: None,
+            examples: DEFAULT_LEAKAGE_EXAMPLES,
+        }
+    }
+
+    #[must_use]
+    pub fn keys(mut self, keys: Vec<Column>) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    #[must_use]
+    pub fn memory(mut self, memory: u64) -> Self {
+        self.memory = memory;
+        self
+    }
+
+    #[must_use]
+    pub fn temp_dir(mut self, temp_dir: PathBuf) -> Self {
+        self.temp_dir = Some(temp_dir);
+        self
+    }
+
+    #[must_use]
+    pub fn examples(mut self, examples: usize) -> Self {
+        self.examples = examples;
+        self
+    }
+
+    pub fn build(self) -> Result<LeakageChecker> {
+        let (manifest, dir) = Manifest::find(&self.path)?;
+        Ok(LeakageChecker {
+            manifest,
+            dir,
+            keys: self.keys,
+            memory: self.memory,
+            temp_dir: self.temp_dir.unwrap_or_else(std::env::temp_dir),
+            examples: self.examples,
+        })
+    }
+}
+
+/// Checks that no key appears in more than one split of a previous run.
+///
+/// Each row's key (its key columns, or the whole row) is hashed, and the
+/// hashes are grouped to find those seen in more than one split. Once the
+/// hashes no longer fit in memory they're partitioned on disk, and each
+/// partition is checked in turn. Examples of leaked keys are found by reading
+/// the outputs a second time.
+pub struct LeakageChecker {
+    manifest: Manifest,
+    /// The directory the manifest's paths are relative to.
+    dir: PathBuf,
+    keys: Vec<Column>,
+    memory: u64,
+    temp_dir: PathBuf,
+    examples: usize,
+}
+
+impl LeakageChecker {
+    /// Write a report of any leaked keys, returning an error if there are any.
+    pub fn run<W: Write>(self, mut out: W) -> Result<()> {
+        let rows: u64 = self.manifest.splits.iter().map(|s| s.rows).sum();
+        let partitions = (rows * std::mem::size_of::<(u128, u32)>() as u64)
+            .div_ceil(self.memory)
+            .clamp(1, MAX_PARTITIONS);
+        let mut hashes = if partitions == 1 {
+            KeyHashes::Memory(Vec::new())
+        } else {
+            info!(
+                "Partitioning key hashes on disk in {}",
+                self.temp_dir.display()
+            );
+            KeyHashes::Disk(Partitions::new(&self.temp_dir, partitions)?)
+        };
+        let (names, skipped) = self.for_each_key(|split, key| hashes.push(hash_key(key), split))?;
+
+        let splits = self.manifest.splits.len();
+        let mut leaks = Leaks::new(splits, self.examples);
+        hashes.for_each_partition(|entries| leaks.add(entries))?;
+
+        let mut examples = HashMap::new();
+        if !leaks.examples.is_empty() {
+            self.for_each_key(|_, key| {
+                let hash = hash_key(key);
+                if leaks.examples.contains_key(&hash) && !examples.contains_key(&hash) {
+                    examples.insert(hash, format_key(&names, key));
+                }
+                Ok(())
+            })?;
+        }
+
+        let split_names: Vec<&str> = self
+            .manifest
+            .splits
+            .iter()
+            .map(|s| s.name.as_str())
+            .collect();
+        let compared = match self.keys.is_empty() {
+            true => "whole rows".to_string(),
+            false => format!("key columns {}", names.join(", ")),
+        };
+        writeln!(
+            out,
+            "Checked {} rows of {} splits, comparing {}",
+            rows, splits, compared
+        )?;
+        if skipped > 0 {
+            writeln!(out, "Skipped {} row(s) with an empty key", skipped)?;
+        }
+        if leaks.keys == 0 {
+            writeln!(out, "No keys appear in more than one split")?;
+            out.flush()?;
+            return Ok(());
+        }
+        writeln!(out, "{} key(s) appear in more than one split", leaks.keys)?;
+        writeln!(out, "Rows with a key found in another split:")?;
+        for (name, rows) in split_names.iter().zip(&leaks.rows) {
+            writeln!(out, "  {}: {}", name, rows)?;
+        }
+        writeln!(out, "Keys shared between splits:")?;
+        for ((a, b), keys) in &leaks.shared {
+            writeln!(
+                out,
+                "  {} and {}: {}",
+                split_names[*a], split_names[*b], keys
+            )?;
+        }
+        writeln!(out, "Examples:")?;
+        for (hash, counts) in &leaks.examples {
+            let key = examples.get(hash).map_or("", String::as_str);
+            let counts: Vec<String> = split_names
+                .iter()
+                .zip(counts)
+                .filter(|(_, count)| **count > 0)
+                .map(|(name, count)| format!("{} ({})", name, count))
+                .collect();
+            writeln!(out, "  {}: {}", key, counts.join(", "))?;
+        }
+        out.flush()?;
+        Err(Error::LeakageFound(leaks.keys))
+    }
+
+    /// Pass the index of the split and the key of every row of every output
+    /// to `f`, returning the names of the key columns and the number of rows
+    /// skipped.
+    ///
+    /// Rows with an empty key, or an empty value in any key column, are
+    /// skipped: an empty value is missing rather than shared, so it would
+    /// otherwise be reported as a leak in every split.
+    fn for_each_key<F>(&self, mut f: F) -> Result<(Vec<String>, u64)>
+    where
+        F: FnMut(usize, &[String]) -> Result<()>,
+    {
+        let options = &self.manifest.options;
+        let has_header = options.outputs_have_header();
+        let mut format = options.format.clone();
+        let mut names: Vec<String> = self.keys.iter().map(Column::to_string).collect();
+        let mut skipped = 0;
+        for (split, summary) in self.manifest.splits.iter().enumerate() {
+            for chunk in &summary.chunks {
+                let path = self.dir.join(&chunk.path);
+                info!("Reading keys from {}", path.display());
+                let checksum = SharedChecksum::default();
+                let mut reader =
+                    open_data(&path, options.output_compression(), &mut format, &checksum)?;
+
+                let fields = match (FieldReader::new(&format), self.keys.is_empty()) {
+                    (_, true) => None,
+                    (Some(fields), false) => Some(fields),
+                    (None, false) => return Err(Error::ColumnsUnsupported),
+                };
+                let mut header = None;
+                if has_header {
+                    if let Some(line) = reader.read_line() {
+                        let line = line?;
+                        if let Some(fields) = &fields {
+                            header = Some(fields.fields(&line)?);
+                        }
+                    }
+                }
+                let indexes = self
+                    .keys
+                    .iter()
+                    .map(|key| key.index(header.as_deref()))
+                    .collect::<Result<Vec<_>>>()?;
+                if let Some(header) = &header {
+                    for (name, index) in names.iter_mut().zip(&indexes) {
+                        if let Some(field) = header.get(*index) {
+                            name.clone_from(field);
+                        }
+                    }
+                }
+
+                while let Some(record) = reader.read_line() {
+                    let record = record?;
+                    let key = match &fields {
+                        None => vec![record.trim_end_matches(['\n', '\r']).to_string()],
+                        Some(fields) => {
+                            let mut values = fields.fields(&record)?;
+                            indexes
+                                .iter()
+                                .zip(&names)
+                                .map(|(i, name)| {
+                                    values.get_mut(*i).map(std::mem::take).ok_or_else(|| {
+                                        Error::MissingColumn(
+                                            name.clone(),
+                                            chunk.path.display().to_string(),
+                                        )
+                                    })
+                                })
+                                .collect::<Result<_>>()?
+                        }
+                    };
+                    if key.iter().any(String::is_empty) {
+                        skipped += 1;
+                        continue;
+                    }
+                    f(split, &key)?;
+                }
+            }
+        }
+        Ok((names, skipped))
+    }
+}
+
+/// Hash the parts of a key, prefixing each with its length so that different
+/// keys can't run together into the same bytes.
+pub(crate) fn hash_key<S: AsRef<str>>(key: &[S]) -> u128 {
+    let mut hasher = Sha256::new();
+    for part in key {
+        let part = part.as_ref();
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part.as_bytes());
+    }
+    u128::from_le_bytes(hasher.finalize()[..16].try_into().unwrap())
+}
+
+/// Format a key for printing: a whole row as it is, or key columns with their
+/// names.
+fn format_key(names: &[String], key: &[String]) -> String {
+    if names.is_empty() {
+        return key.concat();
+    }
+    names
+        .iter()
+        .zip(key)
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The hash of each row's key and the index of its split.
+enum KeyHashes {
+    Memory(Vec<(u128, u32)>),
+    Disk(Partitions),
+}
+
+impl KeyHashes {
+    fn push(&mut self, hash: u128, split: usize) -> Result<()> {
+        match self {
+            KeyHashes::Memory(entries) => entries.push((hash, split as u32)),
+            KeyHashes::Disk(partitions) => partitions.push(hash, split as u32)?,
+        }
+        Ok(())
+    }
+
+    /// Pass each group of entries which could share a hash to `f`.
+    fn for_each_partition<F: FnMut(Vec<(u128, u32)>)>(self, mut f: F) -> Result<()> {
+        match self {
+            KeyHashes::Memory(entries) => f(entries),
+            KeyHashes::Disk(partitions) => partitions.for_each(f)?,
+        }
+        Ok(())
+    }
+}
+
+/// Key hashes scattered between files by their value.
+struct Partitions {
+    dir: TempDir,
+    writers: Vec<BufWriter<File>>,
+}
+
+impl Partitions {
+    fn new(parent: &Path, partitions: u64) -> Result<Self> {
+        let dir = TempDir::new(parent, "leakage")?;
+        let writers = (0..partitions)
+            .map(|p| Ok(BufWriter::new(File::create(dir.path.join(p.to_string()))?)))
+            .collect::<Result<_>>()?;
+        Ok(Partitions { dir, writers })
+    }
+
+    fn push(&mut self, hash: u128, split: u32) -> Result<()> {
+        let partition = (hash % self.writers.len() as u128) as usize;
+        let writer = &mut self.writers[partition];
+        writer.write_all(&hash.to_le_bytes())?;
+        writer.write_all(&split.to_le_bytes())?;
+        Ok(())
+    }
+
+    fn for_each<F: FnMut(Vec<(u128, u32)>)>(self, mut f: F) -> Result<()> {
+        let partitions = self.writers.len();
+        for writer in self.writers {
+            writer.into_inner().map_err(|e| e.into_error())?;
+        }
+        for partition in 0..partitions {
+            let path = self.dir.path.join(partition.to_string());
+            let mut bytes = Vec::new();
+            File::open(&path)?.read_to_end(&mut bytes)?;
+            std::fs::remove_file(&path)?;
+            let entries = bytes
+                .chunks_exact(ENTRY_BYTES)
+                .map(|entry| {
+                    let (hash, split) = entry.split_at(16);
+                    (
+                        u128::from_le_bytes(hash.try_into().unwrap()),
+                        u32::from_le_bytes(split.try_into().unwrap()),
+                    )
+                })
+                .collect();
+            f(entries);
+        }
+        Ok(())
+    }
+}
+
+/// Keys found in more than one split.
+struct Leaks {
+    /// The number of distinct keys.
+    keys: u64,
+    /// The number of rows of each split with a leaked key.
+    rows: Vec<u64>,
+    /// The number of keys shared by each pair of splits.
+    shared: BTreeMap<(usize, usize), u64>,
+    /// The number of examples to keep.
+    max_examples: usize,
+    /// The leaked keys with the smallest hashes, so that the examples don't
+    /// depend on how the hashes were partitioned, with their number of rows in
+    /// each split.
+    examples: BTreeMap<u128, Vec<u64>>,
+}
+
+impl Leaks {
+    fn new(splits: usize, max_examples: usize) -> Self {
+        Leaks {
+            keys: 0,
+            rows: vec![0; splits],
+            shared: BTreeMap::new(),
+            max_examples,
+            examples: BTreeMap::new(),
+        }
+    }
+
+    /// Add any leaked keys among a partition of hashes.
+    fn add(&mut self, mut entries: Vec<(u128, u32)>) {
+        entries.sort_unstable();
+        for group in entries.chunk_by(|a, b| a.0 == b.0) {
+            let (first, last) = (group[0].1, group[group.len() - 1].1);
+            if first == last {
+                continue;
+            }
+            self.keys += 1;
+            let mut counts = vec![0; self.rows.len()];
+            for (_, split) in group {
+                counts[*split as usize] += 1;
+            }
+            let leaked: Vec<usize> = (0..counts.len()).filter(|s| counts[*s] > 0).collect();
+            for (i, a) in leaked.iter().enumerate() {
+                self.rows[*a] += counts[*a];
+                for b in &leaked[i + 1..] {
+                    *self.shared.entry((*a, *b)).or_default() += 1;
+                }
+            }
+            if self.max_examples > 0 {
+                self.examples.insert(group[0].0, counts);
+                if self.examples.len() > self.max_examples {
+                    self.examples.pop_last();
+                }
+            }
+        }
+    }
+}
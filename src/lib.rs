@@ -1,10 +1,36 @@
 pub mod cli;
+mod contamination;
+mod count;
 mod error;
 mod io;
+mod job;
+mod leakage;
+mod merge;
+mod sample;
+mod shuffle;
 mod split;
+mod stats;
+mod verify;
 
 pub use {
+    crate::contamination::{
+        ContaminationChecker, ContaminationCheckerBuilder, DEFAULT_CONTAMINATION_EXAMPLES,
+        DEFAULT_NGRAM, DEFAULT_OVERLAP_THRESHOLD,
+    },
+    crate::count::count,
     crate::error::{Error, Result},
-    crate::io::Compression,
-    crate::split::SplitterBuilder,
+    crate::io::{BgzfReader, Column, Compression, CsvDialect, RecordFormat, RecordSeparator},
+    crate::job::{Job, JobFile},
+    crate::leakage::{
+        LeakageChecker, LeakageCheckerBuilder, DEFAULT_LEAKAGE_EXAMPLES, DEFAULT_LEAKAGE_MEMORY,
+    },
+    crate::merge::{Merger, MergerBuilder},
+    crate::sample::{SampleSize, Sampler, SamplerBuilder},
+    crate::shuffle::{Shuffler, ShufflerBuilder, DEFAULT_SHUFFLE_MEMORY},
+    crate::split::{OutputTemplate, RunDir, SplitterBuilder},
+    crate::stats::{
+        stats, ColumnStats, Difference, LengthBucket, NumericStats, SplitStats, StatsReport,
+        DEFAULT_MAX_DIFFERENCE,
+    },
+    crate::verify::verify,
 };
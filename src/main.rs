@@ -1,7 +1,11 @@
 use clap::StructOpt;
 use jemallocator::Jemalloc;
 
-use ttv::{cli, Compression, Result, SplitterBuilder};
+use log::info;
+use ttv::{
+    cli, Compression, ContaminationCheckerBuilder, Job, JobFile, LeakageCheckerBuilder,
+    MergerBuilder, Result, SampleSize, SamplerBuilder, ShufflerBuilder, SplitterBuilder,
+};
 
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
@@ -11,32 +15,131 @@ fn main() -> Result<()> {
     let opt = cli::Opt::parse();
     match opt.cmd {
         cli::Command::Split(x) => {
-            let mut splitter = SplitterBuilder::new(&x.input, x.rows, x.prop)?;
-            if x.decompress_input {
-                splitter = splitter.input_compression(Compression::GzipCompression);
+            SplitterBuilder::from_job(Job::from(x))?.build()?.run()?;
+        }
+        cli::Command::Run(x) => {
+            for job in JobFile::read(&x.file, &x.overrides)?.select(&x.job)? {
+                info!("Running job {}", job.name.as_deref().unwrap_or_default());
+                SplitterBuilder::from_job(job)?.build()?.run()?;
+            }
+        }
+        cli::Command::Verify(x) => ttv::verify(&x.manifest, std::io::stdout().lock())?,
+        cli::Command::Stats(x) => {
+            let max_difference = x.max_difference.unwrap_or(ttv::DEFAULT_MAX_DIFFERENCE);
+            let report = ttv::stats(&x.manifest, x.label.as_ref(), max_difference)?;
+            let out = std::io::stdout().lock();
+            if x.json {
+                serde_json::to_writer_pretty(out, &report).map_err(std::io::Error::from)?;
+                println!();
+            } else {
+                report.write_table(out)?;
+            }
+        }
+        cli::Command::CheckLeakage(x) => {
+            let mut checker = LeakageCheckerBuilder::new(&x.manifest).keys(x.key);
+            if let Some(memory) = x.memory {
+                checker = checker.memory(memory);
+            }
+            if let Some(temp_dir) = x.temp_dir {
+                checker = checker.temp_dir(temp_dir);
+            }
+            if let Some(examples) = x.examples {
+                checker = checker.examples(examples);
+            }
+            checker.build()?.run(std::io::stdout().lock())?;
+        }
+        cli::Command::CheckContamination(x) => {
+            let mut checker = ContaminationCheckerBuilder::new(&x.manifest, &x.reference)
+                .keys(x.key)
+                .splits(x.splits)
+                .reference_compression(x.records.input_compression())
+                .reference_format(x.records.record_format());
+            if let Some(text) = x.text {
+                checker = checker.text(text);
+            }
+            if let Some(ngram) = x.ngram {
+                checker = checker.ngram(ngram);
+            }
+            if let Some(threshold) = x.threshold {
+                checker = checker.threshold(threshold);
+            }
+            if x.remove {
+                checker = checker.remove(true);
+            }
+            if let Some(examples) = x.examples {
+                checker = checker.examples(examples);
+            }
+            if x.no_header {
+                checker = checker.reference_has_header(false);
+            }
+            checker.build()?.run(std::io::stdout().lock())?;
+        }
+        cli::Command::Merge(x) => {
+            let mut merger = MergerBuilder::new(&x.input).record_format(x.records.record_format());
+            if x.records.decompress_input {
+                merger = merger.input_compression(Compression::GzipCompression);
+            }
+            if let Some(output) = x.output {
+                merger = merger.output(output);
             }
             if x.compress_output {
-                splitter = splitter.output_compression(Compression::GzipCompression);
+                merger = merger.compress_output(true);
             }
-            if x.csv {
-                splitter = splitter.csv(true);
+            if x.check_header {
+                merger = merger.check_header(true);
+            }
+            if x.force {
+                merger = merger.force(true);
             }
             if x.no_header {
-                splitter = splitter.has_header(false);
+                merger = merger.has_header(false);
+            }
+            merger.build()?.run()?;
+        }
+        cli::Command::Count(x) => {
+            let rows = ttv::count(
+                &x.input,
+                x.records.input_compression(),
+                &x.records.record_format(),
+                !x.no_header,
+                x.cache,
+            )?;
+            println!("{}", rows);
+        }
+        cli::Command::Sample(x) => {
+            let size = match (x.rows, x.prop) {
+                (Some(rows), _) => SampleSize::Rows(rows),
+                (None, Some(prop)) => SampleSize::Proportion(prop),
+                (None, None) => unreachable!("clap requires --rows or --prop"),
+            };
+            let mut sampler = SamplerBuilder::new(&x.input, size)?
+                .input_compression(x.records.input_compression())
+                .record_format(x.records.record_format());
+            if x.no_header {
+                sampler = sampler.has_header(false);
             }
             if let Some(seed) = x.seed {
-                splitter = splitter.seed(seed);
+                sampler = sampler.seed(seed);
+            }
+            sampler.build()?.run(std::io::stdout().lock())?;
+        }
+        cli::Command::Shuffle(x) => {
+            let mut shuffler = ShufflerBuilder::new(&x.input)
+                .input_compression(x.records.input_compression())
+                .record_format(x.records.record_format());
+            if x.no_header {
+                shuffler = shuffler.has_header(false);
             }
-            if let Some(output_prefix) = x.output_prefix {
-                splitter = splitter.output_prefix(output_prefix);
+            if let Some(seed) = x.seed {
+                shuffler = shuffler.seed(seed);
             }
-            if let Some(chunk_size) = x.chunk_size {
-                splitter = splitter.chunk_size(chunk_size);
+            if let Some(memory) = x.memory {
+                shuffler = shuffler.memory(memory);
             }
-            if let Some(total_rows) = x.total_rows {
-                splitter = splitter.total_rows(total_rows);
+            if let Some(temp_dir) = x.temp_dir {
+                shuffler = shuffler.temp_dir(temp_dir);
             }
-            splitter.build()?.run()?;
+            shuffler.build()?.run(std::io::stdout().lock())?;
         }
     };
     Ok(())
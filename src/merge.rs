@@ -0,0 +1,337 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+
+use log::info;
+
+use crate::error::{Error, Result};
+use crate::io::{
+    detect_compression, expand_inputs, index_path, open_data, open_output, Compression,
+    GzipCompressor, GzipFormat, OutputWriter, RecordFormat, SharedChecksum,
+};
+use crate::split::{Manifest, OutputFiles};
+
+pub struct MergerBuilder {
+    /// The paths, directories or glob patterns to merge
+    inputs: Vec<PathBuf>,
+    /// The file to write to, or stdout if `None`
+    output: Option<PathBuf>,
+    /// Should the output be gzip compressed?
+    compress_output: bool,
+    /// Compression for every input, rather than detecting it for each one
+    input_compression: Option<Compression>,
+    /// Should the header of every input be checked against the first?
+    check_header: bool,
+    /// May an existing output be overwritten?
+    force: bool,
+    /// How the inputs are divided into records
+    format: RecordFormat,
+    /// Do the inputs have headers?
+    ///
+    /// Note: defaults to true.
+    has_header: bool,
+}
+
+impl MergerBuilder {
+    pub fn new<P: AsRef<Path>>(inputs: &[P]) -> Self {
+        MergerBuilder {
+            inputs: inputs.iter().map(|i| i.as_ref().to_path_buf()).collect(),
+            output: None,
+            compress_output: false,
+            input_compression: None,
+            check_header: false,
+            force: false,
+            format: RecordFormat::Lines,
+            has_header: true,
+        }
+    }
+
+    #[must_use]
+    pub fn output(mut self, output: PathBuf) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    #[must_use]
+    pub fn compress_output(mut self, compress_output: bool) -> Self {
+        self.compress_output = compress_output;
+        self
+    }
+
+    /// Read every input with this compression, rather than detecting it. Only
+    /// needed for gzip compressed stdin, which can't be detected.
+    #[must_use]
+    pub fn input_compression(mut self, input_compression: Compression) -> Self {
+        self.input_compression = Some(input_compression);
+        self
+    }
+
+    #[must_use]
+    pub fn check_header(mut self, check_header: bool) -> Self {
+        self.check_header = check_header;
+        self
+    }
+
+    #[must_use]
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    #[must_use]
+    pub fn record_format(mut self, format: RecordFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    #[must_use]
+    pub fn has_header(mut self, has_header: bool) -> Self {
+        self.has_header = has_header;
+        self
+    }
+
+    pub fn build(self) -> Result<Merger> {
+        // Chunks are numbered, so files matched by a directory or glob pattern
+        // are put in chunk order rather than name order. Manifests, their
+        // checksums and the row indexes of BGZF chunks are skipped, as they
+        // aren't data.
+        let mut inputs = Vec::new();
+        for input in &self.inputs {
+            let mut files = expand_inputs(&[input])?;
+            let mut skipped: HashSet<_> = files.iter().map(index_path).collect();
+            for manifest in files.iter().filter(|f| Manifest::is_manifest(f)) {
+                skipped.insert(manifest.clone());
+                skipped.insert(Manifest::checksums_path(manifest));
+            }
+            if files.len() > 1 {
+                files.retain(|f| !skipped.contains(f));
+                if files.is_empty() {
+                    return Err(Error::NoInputFiles(input.display().to_string()));
+                }
+            }
+            files.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+            inputs.append(&mut files);
+        }
+        if inputs.iter().any(|i| i.to_str() == Some("-")) && inputs.len() > 1 {
+            return Err(Error::StdinWithOtherInputs);
+        }
+        let gzip = match self.compress_output {
+            true => Some(GzipCompressor::new(
+                GzipFormat::Gzip,
+                6,
+                std::thread::available_parallelism().unwrap_or(NonZeroUsize::MIN),
+            )?),
+            false => None,
+        };
+        Ok(Merger {
+            inputs,
+            output: self.output,
+            gzip,
+            input_compression: self.input_compression,
+            check_header: self.check_header,
+            files: OutputFiles::new(false, self.force),
+            has_header: self.has_header && self.format.allows_header(),
+            format: self.format,
+        })
+    }
+}
+
+/// Concatenates files, such as the chunks of a split or several splits,
+/// writing the header only once.
+///
+/// Each input is decompressed if it's gzip compressed, whatever the others
+/// are, unless the input compression is given.
+pub struct Merger {
+    /// The paths of the input files, in the order they're merged
+    inputs: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    /// Compresses the output, if it should be gzip compressed
+    gzip: Option<GzipCompressor>,
+    input_compression: Option<Compression>,
+    check_header: bool,
+    /// The output file, which is removed if merging fails
+    files: OutputFiles,
+    format: RecordFormat,
+    has_header: bool,
+}
+
+impl Merger {
+    /// Merge the inputs into the output file, or stdout.
+    pub fn run(self) -> Result<()> {
+        let output = match &self.output {
+            Some(path) => {
+                self.files.check_new([path])?;
+                let temp = self.files.create(path)?;
+                let written = Arc::new(AtomicU64::new(0));
+                open_output(
+                    temp,
+                    self.gzip.as_ref(),
+                    &written,
+                    &SharedChecksum::default(),
+                )?
+            }
+            None => match &self.gzip {
+                Some(gzip) => gzip.encoder(std::io::stdout()),
+                None => Box::new(std::io::stdout()),
+            },
+        };
+        let result = self.merge(output).and_then(|rows| {
+            if let Some(path) = &self.output {
+                self.files.commit(path)?;
+            }
+            info!("Merged {} rows from {} files", rows, self.inputs.len());
+            Ok(())
+        });
+        if result.is_err() {
+            self.files.remove_all();
+        }
+        result
+    }
+
+    /// Write every input to `output`, returning the number of rows written.
+    fn merge(&self, mut output: OutputWriter) -> Result<u64> {
+        let mut format = self.format.clone();
+        let mut header: Option<String> = None;
+        let mut rows = 0;
+        for path in &self.inputs {
+            info!("Merging {}", path.display());
+            let compression = match self.input_compression {
+                Some(compression) => compression,
+                None => detect_compression(path)?,
+            };
+            let checksum = SharedChecksum::default();
+            let mut reader = open_data(path, compression, &mut format, &checksum)?;
+            if self.has_header {
+                if let Some(line) = reader.read_line() {
+                    let line = line?;
+                    match &header {
+                        None => {
+                            output.write_all(line.as_bytes())?;
+                            header = Some(line);
+                        }
+                        Some(expected) if self.check_header => {
+                            if line.trim_end_matches(['\r', '\n'])
+                                != expected.trim_end_matches(['\r', '\n'])
+                            {
+                                return Err(Error::HeaderMismatch(path.display().to_string()));
+                            }
+                        }
+                        Some(_) => {}
+                    }
+                }
+            }
+            while let Some(record) = reader.read_line() {
+                output.write_all(record?.as_bytes())?;
+                rows += 1;
+            }
+        }
+        output.finish()?;
+        Ok(rows)
+    }
+}
+
+/// Compare two strings, treating runs of digits as numbers so that, e.g.,
+/// `train.9999.csv` comes before `train.10000.csv`. Strings which differ only
+/// in leading zeros are ordered as they are, so that the order is total.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    numeric_cmp(a, b).then_with(|| a.cmp(b))
+}
+
+/// Compare two strings, treating runs of digits as numbers whatever their
+/// leading zeros.
+fn numeric_cmp(mut a: &str, mut b: &str) -> Ordering {
+    loop {
+        let (a_digits, b_digits) = (digit_prefix(a), digit_prefix(b));
+        let ordering = match (a_digits.is_empty(), b_digits.is_empty()) {
+            (false, false) => {
+                let (x, y) = (
+                    a_digits.trim_start_matches('0'),
+                    b_digits.trim_start_matches('0'),
+                );
+                x.len().cmp(&y.len()).then_with(|| x.cmp(y))
+            }
+            _ => match (a.chars().next(), b.chars().next()) {
+                (Some(x), Some(y)) => x.cmp(&y),
+                (x, y) => return x.cmp(&y),
+            },
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+        a = skip_token(a, a_digits.len());
+        b = skip_token(b, b_digits.len());
+    }
+}
+
+/// Skip the run of `digits` digits at the start of `s`, or its first
+/// character if there are none.
+fn skip_token(s: &str, digits: usize) -> &str {
+    match digits {
+        0 => &s[s.chars().next().map_or(0, char::len_utf8)..],
+        n => &s[n..],
+    }
+}
+
+/// The run of ASCII digits at the start of `s`.
+fn digit_prefix(s: &str) -> &str {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    &s[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn natural_cmp_compares_digit_runs_as_numbers() {
+        assert_eq!(
+            natural_cmp("train.9999.csv", "train.10000.csv"),
+            Ordering::Less
+        );
+        assert_eq!(natural_cmp("train.2.csv", "train.10.csv"), Ordering::Less);
+        assert_eq!(natural_cmp("a1b2", "a1b10"), Ordering::Less);
+        assert_eq!(natural_cmp("train.10.csv", "train.10.csv"), Ordering::Equal);
+        assert_eq!(natural_cmp("train.csv", "train.0.csv"), Ordering::Greater);
+        assert_eq!(natural_cmp("train", "train.0"), Ordering::Less);
+    }
+
+    #[test]
+    fn natural_cmp_ignores_leading_zeros() {
+        assert_eq!(
+            natural_cmp("train.0009.csv", "train.10.csv"),
+            Ordering::Less
+        );
+        assert_eq!(
+            natural_cmp("train.0010.csv", "train.9.csv"),
+            Ordering::Greater
+        );
+        // Equal numbers fall back to comparing the strings as they are.
+        assert_eq!(natural_cmp("train.007.csv", "train.7.csv"), Ordering::Less);
+        assert_eq!(
+            natural_cmp("train.7.csv", "train.007.csv"),
+            Ordering::Greater
+        );
+    }
+
+    #[test]
+    fn natural_cmp_is_case_sensitive() {
+        // Other characters are compared by code point, so upper case comes
+        // before lower case.
+        assert_eq!(natural_cmp("Test.csv", "test.csv"), Ordering::Less);
+        assert_eq!(natural_cmp("train.csv", "Test.csv"), Ordering::Greater);
+        assert_eq!(natural_cmp("A10", "a2"), Ordering::Less);
+    }
+
+    #[test]
+    fn natural_cmp_sorts_chunks() {
+        let mut names = vec!["data.10.csv", "data.2.csv", "data.1.csv", "Data.3.csv"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            names,
+            ["Data.3.csv", "data.1.csv", "data.2.csv", "data.10.csv"]
+        );
+    }
+}
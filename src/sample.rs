@@ -0,0 +1,172 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use log::info;
+use rand::prelude::*;
+use rand_chacha::ChaChaRng;
+
+use crate::error::{Error, Result};
+use crate::io::{expand_inputs, Compression, LineReader, MultiReader, RecordFormat};
+
+/// How many rows to sample.
+#[derive(Clone, Copy, Debug)]
+pub enum SampleSize {
+    /// Exactly this many rows, or every row if there are fewer.
+    Rows(u64),
+    /// Each row independently with this probability.
+    Proportion(f64),
+}
+
+pub struct SamplerBuilder {
+    /// The paths, directories or glob patterns to read input from
+    inputs: Vec<PathBuf>,
+    /// How many rows to sample
+    size: SampleSize,
+    /// The seed used for randomisation
+    seed: Option<u64>,
+    /// Compression for input files
+    input_compression: Compression,
+    /// How the input is divided into records
+    format: RecordFormat,
+    /// Does the input have headers?
+    ///
+    /// Note: defaults to true.
+    has_header: bool,
+}
+
+impl SamplerBuilder {
+    pub fn new<P: AsRef<Path>>(inputs: &[P], size: SampleSize) -> Result<Self> {
+        if let SampleSize::Proportion(p) = size {
+            if p.is_nan() {
+                return Err(Error::InvalidProportion(p.to_string()));
+            }
+            if p <= 0.0 {
+                return Err(Error::ProportionTooLow(p.to_string()));
+            }
+            if p > 1.0 {
+                return Err(Error::ProportionTooHigh(p.to_string()));
+            }
+        }
+        Ok(SamplerBuilder {
+            inputs: inputs.iter().map(|i| i.as_ref().to_path_buf()).collect(),
+            size,
+            seed: None,
+            input_compression: Compression::Uncompressed,
+            format: RecordFormat::Lines,
+            has_header: true,
+        })
+    }
+
+    #[must_use]
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    #[must_use]
+    pub fn input_compression(mut self, input_compression: Compression) -> Self {
+        self.input_compression = input_compression;
+        self
+    }
+
+    #[must_use]
+    pub fn record_format(mut self, format: RecordFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    #[must_use]
+    pub fn has_header(mut self, has_header: bool) -> Self {
+        self.has_header = has_header;
+        self
+    }
+
+    pub fn build(self) -> Result<Sampler> {
+        let seed = self.seed.unwrap_or_else(rand::random);
+        info!("Sampling with seed {}", seed);
+        Ok(Sampler {
+            inputs: expand_inputs(&self.inputs)?,
+            size: self.size,
+            rng: ChaChaRng::seed_from_u64(seed),
+            input_compression: self.input_compression,
+            has_header: self.has_header && self.format.allows_header(),
+            format: self.format,
+        })
+    }
+}
+
+/// Streams a uniform random sample of rows from the input.
+pub struct Sampler {
+    /// The paths of the input files, read in order as one dataset
+    inputs: Vec<PathBuf>,
+    /// How many rows to sample
+    size: SampleSize,
+    /// The stateful random number generator.
+    rng: ChaChaRng,
+    /// Compression for input files
+    input_compression: Compression,
+    /// How the input is divided into records
+    format: RecordFormat,
+    /// Does the input have headers?
+    has_header: bool,
+}
+
+impl Sampler {
+    /// Write the header, if any, followed by the sampled rows in the order
+    /// they appear in the input.
+    pub fn run<W: Write>(mut self, mut out: W) -> Result<()> {
+        let mut reader = MultiReader::new(
+            self.inputs.clone(),
+            self.input_compression,
+            self.format.clone(),
+            self.has_header,
+        )?;
+        if self.has_header {
+            match reader.read_line() {
+                Some(header) => out.write_all(header?.as_bytes())?,
+                None => return Err(Error::EmptyFile),
+            }
+        }
+        match self.size {
+            SampleSize::Proportion(p) => {
+                while let Some(record) = reader.read_line() {
+                    let record = record?;
+                    if self.rng.random_bool(p) {
+                        out.write_all(record.as_bytes())?;
+                    }
+                }
+            }
+            SampleSize::Rows(n) => {
+                for (_, record) in self.reservoir(&mut reader, n)? {
+                    out.write_all(record.as_bytes())?;
+                }
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Choose `n` rows uniformly at random using reservoir sampling, so that
+    /// only the sample is held in memory. Rows are returned in input order,
+    /// with their index.
+    fn reservoir(&mut self, reader: &mut impl LineReader, n: u64) -> Result<Vec<(u64, String)>> {
+        let mut reservoir = Vec::new();
+        let mut index = 0;
+        while let Some(record) = reader.read_line() {
+            let record = record?;
+            if index < n {
+                reservoir.push((index, record));
+            } else {
+                // Keep the row with probability n / (index + 1), replacing a
+                // random row already in the sample.
+                let j = self.rng.random_range(0..=index);
+                if j < n {
+                    reservoir[j as usize] = (index, record);
+                }
+            }
+            index += 1;
+        }
+        reservoir.sort_unstable_by_key(|(i, _)| *i);
+        Ok(reservoir)
+    }
+}
@@ -0,0 +1,348 @@
+use std::fs::{remove_file, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use log::info;
+use rand::prelude::*;
+use rand_chacha::ChaChaRng;
+use sha2::{Digest, Sha256};
+
+use crate::error::{Error, Result};
+use crate::io::{expand_inputs, Compression, LineReader, MultiReader, RecordFormat, TempDir};
+
+/// The default amount of memory to hold records in while shuffling.
+pub const DEFAULT_SHUFFLE_MEMORY: u64 = 512 * 1024 * 1024;
+
+/// The number of buckets records are scattered between, each time they're
+/// written to disk.
+const BUCKETS: u64 = 64;
+const BUCKET_BITS: u32 = BUCKETS.trailing_zeros();
+
+/// The deepest level of buckets, after which a bucket is shuffled in memory
+/// however large it is.
+const MAX_DEPTH: u32 = u64::BITS / BUCKET_BITS - 1;
+
+/// An estimate of the memory used by each record, besides its contents.
+const RECORD_OVERHEAD: u64 = 40;
+
+/// Shuffles records using a bounded amount of memory.
+///
+/// Each record is given a random key, and records are returned in key order.
+/// Once the records no longer fit in memory they are scattered into buckets on
+/// disk by the top bits of their key, and each bucket is then sorted in memory
+/// (or scattered again by the next bits, if it's still too large). Since the
+/// order only depends on the keys, the result for a given seed is the same
+/// whatever the memory limit.
+pub(crate) struct ExternalShuffle {
+    rng: ChaChaRng,
+    /// The number of bytes of records to hold in memory.
+    memory: u64,
+    /// The directory to create temporary files in.
+    temp_dir: PathBuf,
+    /// Records held in memory, with their keys.
+    records: Vec<KeyedRecord>,
+    /// An estimate of the memory used by `records`.
+    used: u64,
+    /// The index of the next record, which breaks ties between equal keys.
+    next_index: u64,
+    /// The buckets on disk, once records have stopped fitting in memory.
+    buckets: Option<Buckets>,
+}
+
+impl ExternalShuffle {
+    pub fn new(rng: ChaChaRng, memory: u64, temp_dir: &Path) -> Self {
+        ExternalShuffle {
+            rng,
+            memory,
+            temp_dir: temp_dir.to_path_buf(),
+            records: Vec::new(),
+            used: 0,
+            next_index: 0,
+            buckets: None,
+        }
+    }
+
+    /// Add a record to be shuffled.
+    pub fn push(&mut self, record: String) -> Result<()> {
+        let record = KeyedRecord {
+            key: self.rng.random(),
+            index: self.next_index,
+            record,
+        };
+        self.next_index += 1;
+        if let Some(buckets) = &mut self.buckets {
+            return buckets.write(record, 0);
+        }
+        self.used += record.record.len() as u64 + RECORD_OVERHEAD;
+        self.records.push(record);
+        if self.used > self.memory {
+            info!("Shuffling on disk in {}", self.temp_dir.display());
+            let mut buckets = Buckets::new(TempDir::new(&self.temp_dir, "shuffle")?)?;
+            for record in self.records.drain(..) {
+                buckets.write(record, 0)?;
+            }
+            self.buckets = Some(buckets);
+            self.used = 0;
+        }
+        Ok(())
+    }
+
+    /// Pass every record to `emit`, in shuffled order.
+    pub fn finish<F: FnMut(String) -> Result<()>>(mut self, mut emit: F) -> Result<()> {
+        match self.buckets.take() {
+            None => emit_sorted(std::mem::take(&mut self.records), &mut emit),
+            Some(buckets) => buckets.emit(self.memory, 0, &mut emit),
+        }
+    }
+}
+
+/// Sort records by key and pass them to `emit`.
+fn emit_sorted<F: FnMut(String) -> Result<()>>(
+    mut records: Vec<KeyedRecord>,
+    emit: &mut F,
+) -> Result<()> {
+    records.sort_unstable_by_key(|r| (r.key, r.index));
+    for record in records {
+        emit(record.record)?;
+    }
+    Ok(())
+}
+
+/// A record with the random key which decides its position.
+struct KeyedRecord {
+    key: u64,
+    index: u64,
+    record: String,
+}
+
+impl KeyedRecord {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&self.key.to_le_bytes())?;
+        writer.write_all(&self.index.to_le_bytes())?;
+        writer.write_all(&(self.record.len() as u64).to_le_bytes())?;
+        writer.write_all(self.record.as_bytes())?;
+        Ok(())
+    }
+
+    /// Read the next record, or `None` at the end of the file.
+    fn read<R: Read>(reader: &mut R) -> Result<Option<Self>> {
+        let mut header = [0; 24];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let value = |i: usize| u64::from_le_bytes(header[i * 8..i * 8 + 8].try_into().unwrap());
+        let mut record = vec![0; value(2) as usize];
+        reader.read_exact(&mut record)?;
+        Ok(Some(KeyedRecord {
+            key: value(0),
+            index: value(1),
+            record: String::from_utf8(record).map_err(|e| Error::InvalidRecord(e.to_string()))?,
+        }))
+    }
+}
+
+/// Records scattered between files by the bits of their keys at one depth.
+struct Buckets {
+    dir: TempDir,
+    writers: Vec<BufWriter<File>>,
+}
+
+impl Buckets {
+    fn new(dir: TempDir) -> Result<Self> {
+        let writers = (0..BUCKETS)
+            .map(|b| Ok(BufWriter::new(File::create(dir.path.join(b.to_string()))?)))
+            .collect::<Result<_>>()?;
+        Ok(Buckets { dir, writers })
+    }
+
+    fn write(&mut self, record: KeyedRecord, depth: u32) -> Result<()> {
+        let shift = u64::BITS - BUCKET_BITS * (depth + 1);
+        let bucket = (record.key >> shift) & (BUCKETS - 1);
+        record.write(&mut self.writers[bucket as usize])
+    }
+
+    /// Pass the records in every bucket to `emit`, in key order, holding at
+    /// most about `memory` bytes of records in memory at once.
+    fn emit<F: FnMut(String) -> Result<()>>(
+        self,
+        memory: u64,
+        depth: u32,
+        emit: &mut F,
+    ) -> Result<()> {
+        for writer in self.writers {
+            writer.into_inner().map_err(|e| e.into_error())?;
+        }
+        for bucket in 0..BUCKETS {
+            let path = self.dir.path.join(bucket.to_string());
+            let mut reader = BufReader::new(File::open(&path)?);
+            if path.metadata()?.len() <= memory || depth >= MAX_DEPTH {
+                let mut records = Vec::new();
+                while let Some(record) = KeyedRecord::read(&mut reader)? {
+                    records.push(record);
+                }
+                emit_sorted(records, emit)?;
+            } else {
+                // Still too large, so split the bucket by the next bits.
+                let mut buckets = Buckets::new(TempDir::new(&self.dir.path, "shuffle")?)?;
+                while let Some(record) = KeyedRecord::read(&mut reader)? {
+                    buckets.write(record, depth + 1)?;
+                }
+                buckets.emit(memory, depth + 1, emit)?;
+            }
+            remove_file(&path)?;
+        }
+        Ok(())
+    }
+}
+
+/// How to shuffle the rows of each output of a split.
+#[derive(Clone, Debug)]
+pub(crate) struct ShuffleSettings {
+    /// The seed of the split, from which each output's seed is derived.
+    pub seed: u64,
+    pub memory: u64,
+    pub temp_dir: PathBuf,
+}
+
+impl ShuffleSettings {
+    /// The settings for one of `n` shufflers which may be live at once, each
+    /// given an equal share of the memory.
+    pub fn share(&self, n: u64) -> ShuffleSettings {
+        ShuffleSettings {
+            memory: (self.memory / n.max(1)).max(1),
+            ..self.clone()
+        }
+    }
+
+    /// A shuffler for one output, with its own seed so that the result
+    /// doesn't depend on the order in which outputs are written.
+    pub fn shuffler(&self, split: &str, chunk: Option<u64>) -> ExternalShuffle {
+        let digest = Sha256::digest(format!("{}/{}/{:?}", self.seed, split, chunk));
+        let seed = u64::from_le_bytes(digest[..8].try_into().unwrap());
+        ExternalShuffle::new(ChaChaRng::seed_from_u64(seed), self.memory, &self.temp_dir)
+    }
+}
+
+pub struct ShufflerBuilder {
+    /// The paths, directories or glob patterns to read input from
+    inputs: Vec<PathBuf>,
+    /// The seed used for randomisation
+    seed: Option<u64>,
+    /// The number of bytes of records to hold in memory
+    memory: u64,
+    /// The directory to write temporary files to
+    temp_dir: Option<PathBuf>,
+    /// Compression for input files
+    input_compression: Compression,
+    /// How the input is divided into records
+    format: RecordFormat,
+    /// Does the input have headers?
+    ///
+    /// Note: defaults to true.
+    has_header: bool,
+}
+
+impl ShufflerBuilder {
+    pub fn new<P: AsRef<Path>>(inputs: &[P]) -> Self {
+        ShufflerBuilder {
+            inputs: inputs.iter().map(|i| i.as_ref().to_path_buf()).collect(),
+            seed: None,
+            memory: DEFAULT_SHUFFLE_MEMORY,
+            temp_dir: None,
+            input_compression: Compression::Uncompressed,
+            format: RecordFormat::Lines,
+            has_header: true,
+        }
+    }
+
+    #[must_use]
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    #[must_use]
+    pub fn memory(mut self, memory: u64) -> Self {
+        self.memory = memory;
+        self
+    }
+
+    #[must_use]
+    pub fn temp_dir(mut self, temp_dir: PathBuf) -> Self {
+        self.temp_dir = Some(temp_dir);
+        self
+    }
+
+    #[must_use]
+    pub fn input_compression(mut self, input_compression: Compression) -> Self {
+        self.input_compression = input_compression;
+        self
+    }
+
+    #[must_use]
+    pub fn record_format(mut self, format: RecordFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    #[must_use]
+    pub fn has_header(mut self, has_header: bool) -> Self {
+        self.has_header = has_header;
+        self
+    }
+
+    pub fn build(self) -> Result<Shuffler> {
+        let seed = self.seed.unwrap_or_else(rand::random);
+        info!("Shuffling with seed {}", seed);
+        let temp_dir = self.temp_dir.unwrap_or_else(std::env::temp_dir);
+        Ok(Shuffler {
+            inputs: expand_inputs(&self.inputs)?,
+            shuffle: ExternalShuffle::new(ChaChaRng::seed_from_u64(seed), self.memory, &temp_dir),
+            input_compression: self.input_compression,
+            has_header: self.has_header && self.format.allows_header(),
+            format: self.format,
+        })
+    }
+}
+
+/// Shuffles every row of the input, keeping any header first.
+pub struct Shuffler {
+    /// The paths of the input files, read in order as one dataset
+    inputs: Vec<PathBuf>,
+    shuffle: ExternalShuffle,
+    /// Compression for input files
+    input_compression: Compression,
+    /// How the input is divided into records
+    format: RecordFormat,
+    /// Does the input have headers?
+    has_header: bool,
+}
+
+impl Shuffler {
+    /// Write the header, if any, followed by every row in shuffled order.
+    pub fn run<W: Write>(mut self, mut out: W) -> Result<()> {
+        let mut reader = MultiReader::new(
+            self.inputs.clone(),
+            self.input_compression,
+            self.format.clone(),
+            self.has_header,
+        )?;
+        if self.has_header {
+            match reader.read_line() {
+                Some(header) => out.write_all(header?.as_bytes())?,
+                None => return Err(Error::EmptyFile),
+            }
+        }
+        while let Some(record) = reader.read_line() {
+            self.shuffle.push(record?)?;
+        }
+        self.shuffle.finish(|record| {
+            out.write_all(record.as_bytes())?;
+            Ok(())
+        })?;
+        out.flush()?;
+        Ok(())
+    }
+}
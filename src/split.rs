@@ -1,7 +1,12 @@
+mod manifest;
+mod output;
 mod single;
 mod splits;
 mod splitter;
 mod writer;
 
+pub(crate) use self::manifest::{ChunkSummary, Manifest};
+pub(crate) use self::output::OutputFiles;
+pub use self::output::{OutputTemplate, RunDir};
 pub use self::single::{ProportionSplit, RowSplit};
 pub use self::splitter::SplitterBuilder;
@@ -0,0 +1,249 @@
+use std::fs::{read_dir, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use super::output::RunDir;
+use crate::error::{Error, Result};
+use crate::io::{Compression, RecordFormat};
+
+/// The filename of the manifest, written to the output directory. It's
+/// prefixed with the stem of the outputs, e.g. `data.manifest.json`, so that
+/// runs on different inputs can share a directory.
+pub const MANIFEST_FILENAME: &str = "manifest.json";
+
+/// The filename of the checksums of every output, in the format used by
+/// `sha256sum`. It's prefixed like the manifest, e.g. `data.SHA256SUMS`.
+pub const CHECKSUMS_FILENAME: &str = "SHA256SUMS";
+
+/// The filename of the manifest for outputs named after `stem`.
+pub fn manifest_filename(stem: &str) -> String {
+    match stem {
+        "" => MANIFEST_FILENAME.to_string(),
+        stem => format!("{stem}.{MANIFEST_FILENAME}"),
+    }
+}
+
+/// The filename of the checksums for outputs named after `stem`.
+pub fn checksums_filename(stem: &str) -> String {
+    match stem {
+        "" => CHECKSUMS_FILENAME.to_string(),
+        stem => format!("{stem}.{CHECKSUMS_FILENAME}"),
+    }
+}
+
+/// A machine-readable record of a run: what was asked for, what was read and
+/// what was written.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Manifest {
+    /// The version of ttv which wrote the outputs.
+    pub version: String,
+    /// The seed used for randomisation, even if it wasn't given as an option.
+    pub seed: u64,
+    pub options: Options,
+    pub inputs: Vec<InputSummary>,
+    pub splits: Vec<SplitSummary>,
+}
+
+impl Manifest {
+    /// Read a manifest from the JSON file at `path`.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(file).map_err(std::io::Error::from)?)
+    }
+
+    /// The paths of the manifests given either a manifest or a directory
+    /// containing them, in name order.
+    pub fn paths<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>> {
+        let path = path.as_ref();
+        if !path.is_dir() {
+            return Ok(vec![path.to_path_buf()]);
+        }
+        let mut paths = Vec::new();
+        for entry in read_dir(path)? {
+            let path = entry?.path();
+            if Manifest::is_manifest(&path) {
+                paths.push(path);
+            }
+        }
+        if paths.is_empty() {
+            return Err(Error::NoManifest(path.display().to_string()));
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Is `path` named like a manifest?
+    pub fn is_manifest<P: AsRef<Path>>(path: P) -> bool {
+        let name = path
+            .as_ref()
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        name == MANIFEST_FILENAME || name.ends_with(&format!(".{MANIFEST_FILENAME}"))
+    }
+
+    /// The path of the manifest given either a manifest or a directory
+    /// containing exactly one.
+    pub fn path<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
+        let mut paths = Manifest::paths(&path)?;
+        if paths.len() > 1 {
+            return Err(Error::AmbiguousManifest(
+                path.as_ref().display().to_string(),
+            ));
+        }
+        Ok(paths.remove(0))
+    }
+
+    /// The path of the checksums written alongside the manifest at `path`.
+    pub fn checksums_path<P: AsRef<Path>>(path: P) -> PathBuf {
+        let path = path.as_ref();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let stem = name
+            .strip_suffix(MANIFEST_FILENAME)
+            .unwrap_or_default()
+            .trim_end_matches('.');
+        path.with_file_name(checksums_filename(stem))
+    }
+
+    /// Read the manifest at `path`, which is either a manifest or a directory
+    /// containing exactly one, returning it with the directory its paths are
+    /// relative to.
+    pub fn find<P: AsRef<Path>>(path: P) -> Result<(Self, PathBuf)> {
+        let manifest_path = Manifest::path(path)?;
+        info!("Reading manifest from {}", manifest_path.display());
+        let manifest = Manifest::read(&manifest_path)?;
+        let dir = manifest_path.parent().unwrap_or_else(|| Path::new(""));
+        Ok((manifest, dir.to_path_buf()))
+    }
+
+    /// Write the checksum of every output file to `path`, so that they can
+    /// also be checked with `sha256sum -c`.
+    pub fn write_checksums<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        for chunk in self.splits.iter().flat_map(|s| &s.chunks) {
+            writeln!(file, "{}  {}", chunk.sha256, chunk.path.display())?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Write the manifest as JSON to the file at `path`.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut file, self).map_err(std::io::Error::from)?;
+        writeln!(file)?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+/// The options a split was run with.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Options {
+    pub inputs: Vec<PathBuf>,
+    pub splits: Vec<SplitOption>,
+    pub seed: Option<u64>,
+    pub output_prefix: Option<PathBuf>,
+    pub output_dir: Option<PathBuf>,
+    pub run_dir: Option<RunDir>,
+    pub output_template: Option<String>,
+    pub output_extension: Option<String>,
+    pub chunk_size: Option<u64>,
+    pub chunk_bytes: Option<u64>,
+    pub chunk_bytes_compressed: bool,
+    pub shards: Option<u64>,
+    pub contiguous: bool,
+    pub total_rows: Option<u64>,
+    pub decompress_input: bool,
+    pub compress_output: bool,
+    #[serde(default)]
+    pub compression_level: Option<u32>,
+    #[serde(default)]
+    pub bgzf: bool,
+    #[serde(default)]
+    pub shuffle: bool,
+    pub format: RecordFormat,
+    pub has_header: bool,
+    #[serde(default)]
+    pub fsync: bool,
+    #[serde(default)]
+    pub force: bool,
+}
+
+impl Options {
+    /// The compression of the output files.
+    pub fn output_compression(&self) -> Compression {
+        if self.compress_output {
+            Compression::GzipCompression
+        } else {
+            Compression::Uncompressed
+        }
+    }
+
+    /// Whether each output file starts with a header.
+    pub fn outputs_have_header(&self) -> bool {
+        self.has_header && self.format.allows_header()
+    }
+}
+
+/// A single split, as given in the options.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SplitOption {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proportion: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rows: Option<u64>,
+}
+
+/// The size and checksum of an input file.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct InputSummary {
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+/// The rows and bytes written to each chunk of a split.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SplitSummary {
+    pub name: String,
+    /// The number of rows written, not including headers.
+    pub rows: u64,
+    /// The number of bytes written to files, after any compression.
+    pub bytes: u64,
+    pub chunks: Vec<ChunkSummary>,
+}
+
+impl SplitSummary {
+    pub fn new(name: String, mut chunks: Vec<ChunkSummary>) -> Self {
+        chunks.sort_by_key(|c| c.chunk);
+        SplitSummary {
+            name,
+            rows: chunks.iter().map(|c| c.rows).sum(),
+            bytes: chunks.iter().map(|c| c.bytes).sum(),
+            chunks,
+        }
+    }
+}
+
+/// The rows and bytes written to a single output file.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ChunkSummary {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk: Option<u64>,
+    /// The path of the file, relative to the manifest.
+    pub path: PathBuf,
+    /// The path of the file's row index, if it's BGZF.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<PathBuf>,
+    /// The number of rows written, not including the header.
+    pub rows: u64,
+    /// The number of bytes written to the file, after any compression.
+    pub bytes: u64,
+    /// The SHA-256 checksum of the file.
+    pub sha256: String,
+}
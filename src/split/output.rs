@@ -0,0 +1,653 @@
+use std::collections::HashSet;
+use std::fs::{remove_dir, remove_file, rename, File};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::io::Compression;
+use crate::split::manifest::{checksums_filename, manifest_filename};
+
+/// A value which can be substituted into an output template.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Placeholder {
+    /// The name of the split.
+    Split,
+    /// The input (or output prefix) filename, without extensions.
+    Stem,
+    /// The chunk number, starting at 0.
+    Chunk,
+    /// The total number of chunks in the split.
+    Chunks,
+    /// The file extension, including any compression suffix.
+    Ext,
+    /// The seed used for randomisation.
+    Seed,
+}
+
+impl FromStr for Placeholder {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "split" => Ok(Placeholder::Split),
+            "stem" => Ok(Placeholder::Stem),
+            "chunk" => Ok(Placeholder::Chunk),
+            "chunks" => Ok(Placeholder::Chunks),
+            "ext" => Ok(Placeholder::Ext),
+            "seed" => Ok(Placeholder::Seed),
+            _ => Err(Error::InvalidOutputTemplate(format!(
+                "unknown placeholder {{{s}}}"
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Part {
+    Literal(String),
+    Placeholder {
+        placeholder: Placeholder,
+        fill: char,
+        width: usize,
+    },
+}
+
+/// A template for output paths, relative to the output directory, such as
+/// `{split}/{stem}-{chunk:04}.{ext}`.
+///
+/// Placeholders may be given a width, optionally preceded by a fill character
+/// (which defaults to `0`), e.g. `{chunk:6}` or `{chunk:_6}`. Literal braces
+/// are written as `{{` and `}}`.
+#[derive(Clone, Debug)]
+pub struct OutputTemplate {
+    template: String,
+    parts: Vec<Part>,
+}
+
+impl std::fmt::Display for OutputTemplate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.template)
+    }
+}
+
+impl OutputTemplate {
+    fn uses(&self, placeholder: Placeholder) -> bool {
+        self.parts.iter().any(|p| match p {
+            Part::Placeholder { placeholder: q, .. } => *q == placeholder,
+            Part::Literal(_) => false,
+        })
+    }
+}
+
+impl FromStr for OutputTemplate {
+    type Err = Error;
+
+    fn from_str(template: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut spec = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => spec.push(c),
+                            None => {
+                                return Err(Error::InvalidOutputTemplate(format!(
+                                    "unclosed placeholder in {template}"
+                                )))
+                            }
+                        }
+                    }
+                    let (name, format) = spec.split_once(':').unwrap_or((&spec, ""));
+                    let mut fill = '0';
+                    let mut width = format;
+                    if let Some(first) = format.chars().next() {
+                        if !first.is_ascii_digit() || (first == '0' && format.len() > 1) {
+                            fill = first;
+                            width = &format[first.len_utf8()..];
+                        }
+                    }
+                    let width = if width.is_empty() {
+                        0
+                    } else {
+                        width.parse().map_err(|_| {
+                            Error::InvalidOutputTemplate(format!("invalid width in {{{spec}}}"))
+                        })?
+                    };
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Placeholder {
+                        placeholder: name.parse()?,
+                        fill,
+                        width,
+                    });
+                }
+                '}' => {
+                    return Err(Error::InvalidOutputTemplate(format!(
+                        "unmatched '}}' in {template}"
+                    )))
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(OutputTemplate {
+            template: template.to_string(),
+            parts,
+        })
+    }
+}
+
+/// A fresh directory to write the outputs of a run to, inside the output
+/// directory.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunDir {
+    /// Named after the current UTC time, e.g. `run-20240131T235959Z`.
+    Timestamp,
+    /// Named after the seed, e.g. `seed-42`.
+    Seed,
+}
+
+impl RunDir {
+    /// The name of the directory for a run with the given seed.
+    pub fn name(&self, seed: u64) -> String {
+        match self {
+            RunDir::Timestamp => format!("run-{}", utc_timestamp(SystemTime::now())),
+            RunDir::Seed => format!("seed-{seed}"),
+        }
+    }
+}
+
+impl FromStr for RunDir {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "timestamp" => Ok(RunDir::Timestamp),
+            "seed" => Ok(RunDir::Seed),
+            _ => Err(Error::InvalidRunDir(s.to_string())),
+        }
+    }
+}
+
+/// Format a time as a compact ISO 8601 UTC timestamp, e.g.
+/// `20240131T235959Z`.
+fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // Convert days since the epoch to a civil date, from Howard Hinnant's
+    // `civil_from_days` algorithm.
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// The extension used if none can be inferred from the input filename.
+const DEFAULT_EXTENSION: &str = "csv";
+
+/// Suffixes of compressed files, which are ignored when inferring the
+/// extension of the records inside.
+const COMPRESSION_SUFFIXES: [&str; 4] = ["gz", "gzip", "bgz", "bgzf"];
+
+/// Split a filename into its stem and the extension of the records inside it,
+/// ignoring any compression suffix; e.g. `data.jsonl.gz` is split into `data`
+/// and `jsonl`.
+fn split_extension(filename: &str) -> (String, Option<String>) {
+    let mut stem = filename;
+    if let Some((rest, suffix)) = stem.rsplit_once('.') {
+        if !rest.is_empty() && COMPRESSION_SUFFIXES.contains(&suffix.to_ascii_lowercase().as_str())
+        {
+            stem = rest;
+        }
+    }
+    match stem.rsplit_once('.') {
+        Some((rest, extension)) if !rest.is_empty() && !extension.is_empty() => {
+            (rest.to_string(), Some(extension.to_string()))
+        }
+        _ => (stem.to_string(), None),
+    }
+}
+
+/// Determines the path that each chunk of each split is written to.
+#[derive(Clone, Debug)]
+pub struct OutputLayout {
+    /// The directory containing all outputs.
+    dir: PathBuf,
+    /// The input (or output prefix) filename, without extensions.
+    stem: String,
+    /// The extension for the record format, without any compression suffix.
+    extension: String,
+    compression: Compression,
+    seed: u64,
+    template: Option<OutputTemplate>,
+}
+
+impl OutputLayout {
+    pub fn new(
+        prefix: Option<&Path>,
+        dir: Option<&Path>,
+        run_dir: Option<RunDir>,
+        template: Option<OutputTemplate>,
+        extension: Option<String>,
+        compression: Compression,
+        seed: u64,
+    ) -> Self {
+        let (stem, inferred_extension) = prefix
+            .and_then(|p| p.file_name())
+            .map(|f| split_extension(&f.to_string_lossy()))
+            .unwrap_or_default();
+        let extension = extension
+            .or(inferred_extension)
+            .unwrap_or_else(|| DEFAULT_EXTENSION.to_string());
+        let mut dir = match (dir, prefix) {
+            (Some(dir), _) => dir.to_path_buf(),
+            (None, Some(prefix)) => prefix.parent().map(Path::to_path_buf).unwrap_or_default(),
+            (None, None) => PathBuf::new(),
+        };
+        if let Some(run_dir) = run_dir {
+            dir.push(run_dir.name(seed));
+        }
+        OutputLayout {
+            dir,
+            stem,
+            extension,
+            compression,
+            seed,
+            template,
+        }
+    }
+
+    /// Whether the layout needs a filename stem, i.e. whether an input or
+    /// output prefix filename is required.
+    pub fn needs_stem(&self) -> bool {
+        self.template
+            .as_ref()
+            .map(|t| t.uses(Placeholder::Stem))
+            .unwrap_or(true)
+    }
+
+    /// The full extension of output files, including any compression suffix.
+    fn extension(&self) -> String {
+        match self.compression {
+            Compression::GzipCompression => format!("{}.gz", self.extension),
+            Compression::Uncompressed => self.extension.clone(),
+        }
+    }
+
+    /// Get the path for a chunk of a split.
+    ///
+    /// `chunk` is `None` if the split isn't chunked, and `chunks` is `None`
+    /// if the number of chunks isn't known in advance.
+    pub fn path(&self, split: &str, chunk: Option<u64>, chunks: Option<u64>) -> Result<PathBuf> {
+        let template = match &self.template {
+            Some(t) => t,
+            None => {
+                let chunk_part = match chunk {
+                    None => "".to_string(),
+                    Some(c) => format!(".{c:0>4}"),
+                };
+                return Ok(self.dir.join(split).join(format!(
+                    "{}.{}{}.{}",
+                    self.stem,
+                    split,
+                    chunk_part,
+                    self.extension(),
+                )));
+            }
+        };
+        let mut path = String::new();
+        for part in &template.parts {
+            let (placeholder, fill, width) = match part {
+                Part::Literal(s) => {
+                    path.push_str(s);
+                    continue;
+                }
+                Part::Placeholder {
+                    placeholder,
+                    fill,
+                    width,
+                } => (placeholder, *fill, *width),
+            };
+            let value = match placeholder {
+                Placeholder::Split => split.to_string(),
+                Placeholder::Stem => self.stem.clone(),
+                Placeholder::Chunk => chunk.unwrap_or(0).to_string(),
+                Placeholder::Chunks => match (chunk, chunks) {
+                    (None, _) => "1".to_string(),
+                    (Some(_), Some(n)) => n.to_string(),
+                    (Some(_), None) => return Err(Error::InvalidOutputTemplate(
+                        "{chunks} can't be used when the number of chunks isn't known in advance"
+                            .to_string(),
+                    )),
+                },
+                Placeholder::Ext => self.extension(),
+                Placeholder::Seed => self.seed.to_string(),
+            };
+            for _ in value.chars().count()..width {
+                path.push(fill);
+            }
+            path.push_str(&value);
+        }
+        Ok(self.dir.join(path))
+    }
+
+    /// The path of the manifest, named after the outputs.
+    pub fn manifest_path(&self) -> PathBuf {
+        self.dir.join(manifest_filename(&self.stem))
+    }
+
+    /// The path of the checksums of the outputs, named after them.
+    pub fn checksums_path(&self) -> PathBuf {
+        self.dir.join(checksums_filename(&self.stem))
+    }
+
+    /// A path relative to the output directory, if it's inside it.
+    pub fn relative_path(&self, path: &Path) -> PathBuf {
+        path.strip_prefix(&self.dir).unwrap_or(path).to_path_buf()
+    }
+
+    /// Check that no two chunks will be written to the same path, returning
+    /// the paths.
+    ///
+    /// Each item of `chunks` is a split name, a chunk number and the number
+    /// of chunks in the split, as passed to `path`.
+    pub fn validate<'a>(
+        &self,
+        chunks: impl IntoIterator<Item = (&'a str, Option<u64>, Option<u64>)>,
+    ) -> Result<Vec<PathBuf>> {
+        let mut seen = HashSet::new();
+        let mut paths = Vec::new();
+        for (split, chunk, n_chunks) in chunks {
+            let path = self.path(split, chunk, n_chunks)?;
+            if !seen.insert(path.clone()) {
+                return Err(Error::DuplicateOutputPath(path.display().to_string()));
+            }
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+}
+
+/// Keeps track of the files written by a run, so that each one only appears
+/// under its final name once it's complete, and so that every output can be
+/// removed if the run fails.
+///
+/// Files are written to a hidden temporary file in the same directory, then
+/// renamed into place.
+#[derive(Debug)]
+pub struct OutputFiles {
+    /// Whether to sync files to disk before renaming them into place.
+    fsync: bool,
+    /// Whether existing files may be overwritten.
+    force: bool,
+    state: Mutex<OutputFilesState>,
+}
+
+#[derive(Debug, Default)]
+struct OutputFilesState {
+    /// The final paths of every file created.
+    paths: Vec<PathBuf>,
+    /// The final paths of files which have been moved into place.
+    committed: Vec<PathBuf>,
+    /// Whether the run has failed, in which case nothing more may be written.
+    aborted: bool,
+}
+
+/// The temporary path a file is written to before being renamed to `path`.
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|f| f.to_string_lossy())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{name}.tmp"))
+}
+
+impl OutputFiles {
+    pub fn new(fsync: bool, force: bool) -> Self {
+        OutputFiles {
+            fsync,
+            force,
+            state: Default::default(),
+        }
+    }
+
+    /// Check that none of `paths` already exist, unless existing files may
+    /// be overwritten.
+    pub fn check_new<P: AsRef<Path>>(&self, paths: impl IntoIterator<Item = P>) -> Result<()> {
+        if self.force {
+            return Ok(());
+        }
+        for path in paths {
+            if path.as_ref().exists() {
+                return Err(Error::OutputExists(path.as_ref().display().to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Register a file which is about to be written, returning the temporary
+    /// path to write it to.
+    pub fn create(&self, path: &Path) -> Result<PathBuf> {
+        let mut state = self.state.lock().unwrap();
+        if state.aborted {
+            return Err(Error::RunAborted);
+        }
+        state.paths.push(path.to_path_buf());
+        Ok(temp_path(path))
+    }
+
+    /// Move a file which has been completely written into place.
+    pub fn commit(&self, path: &Path) -> Result<()> {
+        let temp = temp_path(path);
+        if self.fsync {
+            File::open(&temp)?.sync_all()?;
+        }
+        // Hold the lock while renaming, so that a file can't be renamed into
+        // place after the outputs have been removed.
+        let mut state = self.state.lock().unwrap();
+        if state.aborted {
+            let _ = remove_file(&temp);
+            return Err(Error::RunAborted);
+        }
+        if !self.force && path.exists() {
+            // Only possible for chunks whose paths weren't known up front.
+            let _ = remove_file(&temp);
+            return Err(Error::OutputExists(path.display().to_string()));
+        }
+        rename(&temp, path)?;
+        state.committed.push(path.to_path_buf());
+        if self.fsync {
+            sync_dir(path)?;
+        }
+        Ok(())
+    }
+
+    /// Move every file which has been written but not yet committed into
+    /// place, in the order they were created.
+    pub fn commit_all(&self) -> Result<()> {
+        let pending: Vec<PathBuf> = {
+            let state = self.state.lock().unwrap();
+            let committed: HashSet<&PathBuf> = state.committed.iter().collect();
+            state
+                .paths
+                .iter()
+                .filter(|p| !committed.contains(p))
+                .cloned()
+                .collect()
+        };
+        for path in pending {
+            self.commit(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Remove every file written (or being written), along with any
+    /// directories left empty, and stop any more files being written.
+    ///
+    /// Files which weren't written by this run are left alone.
+    pub fn remove_all(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.aborted = true;
+        let temps = state.paths.iter().map(|p| temp_path(p));
+        for path in temps.chain(state.committed.iter().cloned()) {
+            match remove_file(&path) {
+                Ok(()) => debug!("Removed {}", path.display()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => warn!("Couldn't remove {}: {}", path.display(), e),
+            }
+        }
+        for path in &state.paths {
+            if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+                // This only succeeds if nothing else is in the directory.
+                let _ = remove_dir(dir);
+            }
+        }
+    }
+}
+
+/// Sync the directory containing `path`, so that a rename is durable.
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The path of a chunk of the train split of `data.csv` for `template`.
+    fn path(template: &str, chunk: Option<u64>, chunks: Option<u64>) -> Result<String> {
+        let layout = OutputLayout::new(
+            Some(Path::new("data.csv")),
+            None,
+            None,
+            Some(template.parse()?),
+            None,
+            Compression::Uncompressed,
+            42,
+        );
+        Ok(layout.path("train", chunk, chunks)?.display().to_string())
+    }
+
+    /// The message of the error from parsing or filling in a template.
+    fn error(result: Result<String>) -> String {
+        match result {
+            Err(Error::InvalidOutputTemplate(message)) => message,
+            other => panic!("expected an invalid template, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn template_fills_in_placeholders() {
+        assert_eq!(
+            path(
+                "{split}/{stem}-{chunk}-of-{chunks}.{seed}.{ext}",
+                Some(3),
+                Some(10)
+            )
+            .unwrap(),
+            "train/data-3-of-10.42.csv"
+        );
+        // Without chunks, the only chunk is 0 of 1.
+        assert_eq!(
+            path("{split}.{chunk}.{chunks}", None, None).unwrap(),
+            "train.0.1"
+        );
+    }
+
+    #[test]
+    fn template_pads_to_width() {
+        assert_eq!(path("{chunk:03}", Some(7), None).unwrap(), "007");
+        assert_eq!(path("{chunk:3}", Some(7), None).unwrap(), "007");
+        assert_eq!(path("{chunk:_4}", Some(7), None).unwrap(), "___7");
+        assert_eq!(path("{chunk:03}", Some(1234), None).unwrap(), "1234");
+        assert_eq!(
+            error(path("{chunk:0x}", None, None)),
+            "invalid width in {chunk:0x}"
+        );
+    }
+
+    #[test]
+    fn template_rejects_unknown_placeholders() {
+        assert_eq!(
+            error(path("{split}/{name}.{ext}", None, None)),
+            "unknown placeholder {name}"
+        );
+        assert_eq!(error(path("{}", None, None)), "unknown placeholder {}");
+    }
+
+    #[test]
+    fn template_rejects_unbalanced_braces() {
+        assert_eq!(
+            error(path("{split/{chunk}", None, None)),
+            "unknown placeholder {split/{chunk}"
+        );
+        assert_eq!(
+            error(path("{split", None, None)),
+            "unclosed placeholder in {split"
+        );
+        assert_eq!(error(path("split}", None, None)), "unmatched '}' in split}");
+    }
+
+    #[test]
+    fn template_keeps_escaped_braces() {
+        assert_eq!(path("{{split}}", None, None).unwrap(), "{split}");
+        assert_eq!(
+            path("{{{split}}}.{ext}", None, None).unwrap(),
+            "{train}.csv"
+        );
+    }
+
+    #[test]
+    fn template_needs_known_chunks() {
+        let message = error(path("{chunk}-of-{chunks}", Some(0), None));
+        assert!(message.starts_with("{chunks} can't be used"));
+    }
+}
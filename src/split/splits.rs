@@ -13,7 +13,7 @@ pub enum SplitSelection<'a> {
 }
 
 pub trait SplitSelector {
-    fn get_split(&mut self, rng: &mut ChaChaRng) -> SplitSelection;
+    fn get_split(&mut self, rng: &mut ChaChaRng) -> SplitSelection<'_>;
 }
 
 /// Splits defined using proportions.
@@ -23,7 +23,7 @@ pub struct ProportionSplits {
 }
 
 impl SplitSelector for ProportionSplits {
-    fn get_split(&mut self, rng: &mut ChaChaRng) -> SplitSelection {
+    fn get_split(&mut self, rng: &mut ChaChaRng) -> SplitSelection<'_> {
         let random: f64 = rng.random();
         let mut total = 0.0;
         for split in &self.splits {
@@ -63,7 +63,7 @@ pub struct RowSplits {
 }
 
 impl SplitSelector for RowSplits {
-    fn get_split(&mut self, rng: &mut ChaChaRng) -> SplitSelection {
+    fn get_split(&mut self, rng: &mut ChaChaRng) -> SplitSelection<'_> {
         let random: f64 = rng.random();
         let random = random * self.total;
 
@@ -117,7 +117,7 @@ impl Deref for Splits {
 
 impl Splits {
     /// Get a random split.
-    pub fn get_split(&mut self, rng: &mut ChaChaRng) -> SplitSelection {
+    pub fn get_split(&mut self, rng: &mut ChaChaRng) -> SplitSelection<'_> {
         match self {
             Splits::Rows(rows) => rows.get_split(rng),
             Splits::Proportions(rows) => rows.get_split(rng),
@@ -1,38 +1,78 @@
 use std::collections::HashMap;
+use std::num::{NonZeroU64, NonZeroUsize};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use log::{debug, info};
+use log::info;
 use rand::prelude::*;
 use rand_chacha::ChaChaRng;
 
 use crate::error::{Error, Result};
-use crate::io::{open_data, Compression};
+use crate::io::{
+    expand_inputs, index_path, Compression, CsvDialect, GzipCompressor, GzipFormat, LineReader,
+    MultiReader, RecordFormat,
+};
+use crate::job::Job;
+use crate::shuffle::{ShuffleSettings, DEFAULT_SHUFFLE_MEMORY};
 use crate::split::{
+    manifest::{InputSummary, Manifest, Options, SplitOption},
+    output::{OutputFiles, OutputLayout, OutputTemplate, RunDir},
     single::{ProportionSplit, RowSplit, Split, SplitEnum},
     splits::{SplitSelection, Splits},
-    writer::SplitWriter,
+    writer::{ByteLimit, ChunkPlan, SplitWriter},
 };
 
 pub struct SplitterBuilder {
-    /// The path to the input file
-    input: PathBuf,
+    /// The paths, directories or glob patterns to read input from
+    inputs: Vec<PathBuf>,
     /// The desired splits
     splits: Splits,
     /// The seed used for randomisation
     seed: Option<u64>,
     /// The prefix for the output file(s)
     output_prefix: Option<PathBuf>,
+    /// The directory to write outputs to
+    output_dir: Option<PathBuf>,
+    /// A fresh directory for this run, inside the output directory
+    run_dir: Option<RunDir>,
+    /// The template for output paths, relative to the output directory
+    output_template: Option<OutputTemplate>,
+    /// The extension of output files, without any compression suffix
+    output_extension: Option<String>,
     /// The maximum size of each chunk
     chunk_size: Option<u64>,
+    /// The maximum size of each chunk in bytes
+    chunk_bytes: Option<ByteLimit>,
+    /// The exact number of chunks per split
+    shards: Option<NonZeroU64>,
     /// The total number of rows
     total_rows: Option<u64>,
+    /// Should each chunk get a consecutive run of rows?
+    contiguous: bool,
     /// Compression for input files
     input_compression: Compression,
     /// Compression for output files
     output_compression: Compression,
-    /// Is the input CSV?
-    csv: bool,
+    /// Should output files be BGZF, with a row index?
+    bgzf: bool,
+    /// The gzip compression level for output files, from 0 to 9
+    compression_level: u32,
+    /// The number of threads used to compress output files
+    compression_threads: NonZeroUsize,
+    /// Should the rows of each output be shuffled?
+    shuffle: bool,
+    /// The number of bytes of rows to hold in memory when shuffling, shared by
+    /// the outputs being written
+    shuffle_memory: u64,
+    /// The directory to write temporary files to when shuffling
+    temp_dir: Option<PathBuf>,
+    /// Should outputs be synced to disk before being moved into place?
+    fsync: bool,
+    /// May existing outputs be overwritten?
+    force: bool,
+    /// How the input is divided into records
+    format: RecordFormat,
     /// Does the input have headers?
     ///
     /// Note: defaults to true.
@@ -45,25 +85,113 @@ impl SplitterBuilder {
         row_splits: Vec<RowSplit>,
         prop_splits: Vec<ProportionSplit>,
     ) -> Result<Self> {
+        SplitterBuilder::new_multi(std::slice::from_ref(input), row_splits, prop_splits)
+    }
+
+    /// Create a builder which reads several inputs in order, as one dataset.
+    pub fn new_multi<P: AsRef<Path>>(
+        inputs: &[P],
+        row_splits: Vec<RowSplit>,
+        prop_splits: Vec<ProportionSplit>,
+    ) -> Result<Self> {
+        if inputs.is_empty() {
+            return Err(Error::NoInputs);
+        }
         let splits = if row_splits.is_empty() {
             Splits::Proportions(prop_splits.try_into()?)
         } else {
             Splits::Rows(row_splits.into())
         };
         Ok(SplitterBuilder {
-            input: input.as_ref().to_path_buf(),
+            inputs: inputs.iter().map(|i| i.as_ref().to_path_buf()).collect(),
             splits,
             seed: None,
             output_prefix: None,
+            output_dir: None,
+            run_dir: None,
+            output_template: None,
+            output_extension: None,
             chunk_size: None,
+            chunk_bytes: None,
+            shards: None,
             total_rows: None,
+            contiguous: false,
             input_compression: Compression::Uncompressed,
             output_compression: Compression::Uncompressed,
-            csv: false,
+            bgzf: false,
+            compression_level: 6,
+            compression_threads: std::thread::available_parallelism().unwrap_or(NonZeroUsize::MIN),
+            shuffle: false,
+            shuffle_memory: DEFAULT_SHUFFLE_MEMORY,
+            temp_dir: None,
+            fsync: false,
+            force: false,
+            format: RecordFormat::Lines,
             has_header: true,
         })
     }
 
+    /// Create a builder from a job, as read from a job file or given to the
+    /// `split` command.
+    pub fn from_job(job: Job) -> Result<Self> {
+        let records = job.record_options();
+        let mut splitter = SplitterBuilder::new_multi(&job.input, job.rows, job.prop)?
+            .input_compression(records.input_compression())
+            .record_format(records.record_format())
+            .has_header(!job.no_header)
+            .bgzf(job.bgzf)
+            .contiguous(job.contiguous)
+            .shuffle(job.shuffle)
+            .fsync(job.fsync)
+            .force(job.force);
+        if job.compress_output {
+            splitter = splitter.output_compression(Compression::GzipCompression);
+        }
+        if let Some(level) = job.compression_level {
+            splitter = splitter.compression_level(level);
+        }
+        if let Some(threads) = job.compression_threads {
+            splitter = splitter.compression_threads(threads);
+        }
+        if let Some(seed) = job.seed {
+            splitter = splitter.seed(seed);
+        }
+        if let Some(output_prefix) = job.output_prefix {
+            splitter = splitter.output_prefix(output_prefix);
+        }
+        if let Some(output_dir) = job.output_dir {
+            splitter = splitter.output_dir(output_dir);
+        }
+        if let Some(output_template) = job.output_template {
+            splitter = splitter.output_template(output_template);
+        }
+        if let Some(output_extension) = job.output_extension {
+            splitter = splitter.output_extension(output_extension);
+        }
+        if let Some(chunk_size) = job.chunk_size {
+            splitter = splitter.chunk_size(chunk_size.get());
+        }
+        if let Some(chunk_bytes) = job.chunk_bytes {
+            splitter = splitter.chunk_bytes(chunk_bytes, job.chunk_bytes_compressed);
+        }
+        if let Some(shards) = job.shards {
+            splitter = splitter.shards(shards);
+        }
+        if let Some(total_rows) = job.total_rows {
+            splitter = splitter.total_rows(total_rows);
+        }
+        if let Some(memory) = job.shuffle_memory {
+            splitter = splitter.shuffle_memory(memory);
+        }
+        if let Some(temp_dir) = job.temp_dir {
+            splitter = splitter.temp_dir(temp_dir);
+        }
+        if let Some(run_dir) = job.run_dir {
+            splitter = splitter.run_dir(run_dir);
+        }
+        Ok(splitter)
+    }
+
     #[must_use]
     pub fn seed(mut self, seed: u64) -> Self {
         self.seed = Some(seed);
@@ -76,18 +204,66 @@ impl SplitterBuilder {
         self
     }
 
+    #[must_use]
+    pub fn output_dir(mut self, output_dir: PathBuf) -> Self {
+        self.output_dir = Some(output_dir);
+        self
+    }
+
+    #[must_use]
+    pub fn run_dir(mut self, run_dir: RunDir) -> Self {
+        self.run_dir = Some(run_dir);
+        self
+    }
+
+    #[must_use]
+    pub fn output_template(mut self, output_template: OutputTemplate) -> Self {
+        self.output_template = Some(output_template);
+        self
+    }
+
+    #[must_use]
+    pub fn output_extension(mut self, output_extension: String) -> Self {
+        self.output_extension = Some(output_extension.trim_start_matches('.').to_string());
+        self
+    }
+
     #[must_use]
     pub fn chunk_size(mut self, chunk_size: u64) -> Self {
         self.chunk_size = Some(chunk_size);
         self
     }
 
+    /// Move on to a new chunk once the current one reaches `bytes`.
+    ///
+    /// If `compressed`, the limit applies to the compressed size. That is
+    /// only known as each block of output is compressed, and rows still
+    /// queued for the chunk aren't counted, so a chunk may overshoot the limit
+    /// by a few blocks.
+    #[must_use]
+    pub fn chunk_bytes(mut self, bytes: u64, compressed: bool) -> Self {
+        self.chunk_bytes = Some(ByteLimit { bytes, compressed });
+        self
+    }
+
+    #[must_use]
+    pub fn shards(mut self, shards: NonZeroU64) -> Self {
+        self.shards = Some(shards);
+        self
+    }
+
     #[must_use]
     pub fn total_rows(mut self, total_rows: u64) -> Self {
         self.total_rows = Some(total_rows);
         self
     }
 
+    #[must_use]
+    pub fn contiguous(mut self, contiguous: bool) -> Self {
+        self.contiguous = contiguous;
+        self
+    }
+
     #[must_use]
     pub fn input_compression(mut self, input_compression: Compression) -> Self {
         self.input_compression = input_compression;
@@ -100,9 +276,81 @@ impl SplitterBuilder {
         self
     }
 
+    /// Write BGZF output files, and a row index next to each one. Implies gzip
+    /// compressed output.
+    #[must_use]
+    pub fn bgzf(mut self, bgzf: bool) -> Self {
+        self.bgzf = bgzf;
+        if bgzf {
+            self.output_compression = Compression::GzipCompression;
+        }
+        self
+    }
+
+    #[must_use]
+    pub fn compression_level(mut self, compression_level: u32) -> Self {
+        self.compression_level = compression_level;
+        self
+    }
+
+    #[must_use]
+    pub fn compression_threads(mut self, compression_threads: NonZeroUsize) -> Self {
+        self.compression_threads = compression_threads;
+        self
+    }
+
+    /// Shuffle the rows of each output, rather than keeping them in input
+    /// order. Rows beyond `shuffle_memory` bytes, shared by the outputs being
+    /// written, are shuffled on disk.
+    #[must_use]
+    pub fn shuffle(mut self, shuffle: bool) -> Self {
+        self.shuffle = shuffle;
+        self
+    }
+
+    #[must_use]
+    pub fn shuffle_memory(mut self, shuffle_memory: u64) -> Self {
+        self.shuffle_memory = shuffle_memory;
+        self
+    }
+
+    #[must_use]
+    pub fn temp_dir(mut self, temp_dir: PathBuf) -> Self {
+        self.temp_dir = Some(temp_dir);
+        self
+    }
+
+    #[must_use]
+    pub fn fsync(mut self, fsync: bool) -> Self {
+        self.fsync = fsync;
+        self
+    }
+
+    #[must_use]
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
     #[must_use]
     pub fn csv(mut self, csv: bool) -> Self {
-        self.csv = csv;
+        self.format = match (csv, self.format) {
+            (true, RecordFormat::Csv(dialect)) => RecordFormat::Csv(dialect),
+            (true, _) => RecordFormat::Csv(CsvDialect::default()),
+            (false, _) => RecordFormat::Lines,
+        };
+        self
+    }
+
+    #[must_use]
+    pub fn csv_dialect(mut self, csv_dialect: CsvDialect) -> Self {
+        self.format = RecordFormat::Csv(csv_dialect);
+        self
+    }
+
+    #[must_use]
+    pub fn record_format(mut self, format: RecordFormat) -> Self {
+        self.format = format;
         self
     }
 
@@ -112,45 +360,158 @@ impl SplitterBuilder {
         self
     }
 
+    /// The options to record in the manifest.
+    fn options(&self) -> Options {
+        let splits = match &self.splits {
+            Splits::Proportions(p) => p
+                .iter()
+                .map(|s| SplitOption {
+                    name: s.name().to_string(),
+                    proportion: Some(s.proportion),
+                    rows: None,
+                })
+                .collect(),
+            Splits::Rows(r) => r
+                .iter()
+                .map(|s| SplitOption {
+                    name: s.name().to_string(),
+                    proportion: None,
+                    rows: Some(s.total as u64),
+                })
+                .collect(),
+        };
+        Options {
+            inputs: self.inputs.clone(),
+            splits,
+            seed: self.seed,
+            output_prefix: self.output_prefix.clone(),
+            output_dir: self.output_dir.clone(),
+            run_dir: self.run_dir,
+            output_template: self.output_template.as_ref().map(|t| t.to_string()),
+            output_extension: self.output_extension.clone(),
+            chunk_size: self.chunk_size,
+            chunk_bytes: self.chunk_bytes.map(|b| b.bytes),
+            chunk_bytes_compressed: self.chunk_bytes.map(|b| b.compressed).unwrap_or(false),
+            shards: self.shards.map(NonZeroU64::get),
+            contiguous: self.contiguous,
+            total_rows: self.total_rows,
+            decompress_input: matches!(self.input_compression, Compression::GzipCompression),
+            compress_output: matches!(self.output_compression, Compression::GzipCompression),
+            compression_level: matches!(self.output_compression, Compression::GzipCompression)
+                .then_some(self.compression_level),
+            bgzf: self.bgzf,
+            shuffle: self.shuffle,
+            format: self.format.clone(),
+            has_header: self.has_header,
+            fsync: self.fsync,
+            force: self.force,
+        }
+    }
+
     pub fn build(self) -> Result<Splitter> {
-        let rng = match self.seed {
-            Some(s) => ChaChaRng::seed_from_u64(s),
-            None => ChaChaRng::from_os_rng(),
+        let options = self.options();
+        let inputs = expand_inputs(&self.inputs)?;
+        // Output filenames are based on the input filename by default, which
+        // only makes sense if there's exactly one input file.
+        let prefix = match self.output_prefix {
+            Some(prefix) => Some(prefix),
+            None if inputs.len() == 1 && inputs[0].to_str() != Some("-") => Some(inputs[0].clone()),
+            None => None,
+        };
+        // Pick a seed if we weren't given one, so that it can be reported.
+        let seed = self.seed.unwrap_or_else(rand::random);
+        let layout = OutputLayout::new(
+            prefix.as_deref(),
+            self.output_dir.as_deref(),
+            self.run_dir,
+            self.output_template,
+            self.output_extension,
+            self.output_compression,
+            seed,
+        );
+        if prefix.is_none() && layout.needs_stem() {
+            return Err(Error::OutputPrefixRequired);
+        }
+        if self.chunk_size == Some(0) || self.chunk_bytes.is_some_and(|b| b.bytes == 0) {
+            return Err(Error::InvalidSize("0".to_string()));
+        }
+        // Shuffled rows are only written once the chunk is closed, so the
+        // compressed size isn't known until it's too late to roll over.
+        if self.shuffle && self.chunk_bytes.is_some_and(|b| b.compressed) {
+            return Err(Error::ShuffleWithCompressedChunkBytes);
+        }
+        let shuffle = self.shuffle.then(|| ShuffleSettings {
+            seed,
+            memory: self.shuffle_memory,
+            temp_dir: self.temp_dir.unwrap_or_else(std::env::temp_dir),
+        });
+        let gzip = match self.output_compression {
+            Compression::GzipCompression => Some(Arc::new(GzipCompressor::new(
+                if self.bgzf {
+                    GzipFormat::Bgzf
+                } else {
+                    GzipFormat::Gzip
+                },
+                self.compression_level,
+                self.compression_threads,
+            )?)),
+            Compression::Uncompressed => None,
         };
         Ok(Splitter {
-            input: self.input,
-            rng,
+            inputs,
+            options,
+            seed,
+            rng: ChaChaRng::seed_from_u64(seed),
             splits: self.splits,
-            output_prefix: self.output_prefix,
+            layout: Arc::new(layout),
+            files: Arc::new(OutputFiles::new(self.fsync, self.force)),
             chunk_size: self.chunk_size,
+            chunk_bytes: self.chunk_bytes,
+            shards: self.shards,
             total_rows: self.total_rows,
+            contiguous: self.contiguous,
             input_compression: self.input_compression,
-            output_compression: self.output_compression,
-            csv: self.csv,
-            has_header: self.has_header,
+            gzip,
+            shuffle,
+            has_header: self.has_header && self.format.allows_header(),
+            format: self.format,
         })
     }
 }
 
 pub struct Splitter {
-    /// The path to the input file
-    input: PathBuf,
+    /// The paths of the input files, read in order as one dataset
+    inputs: Vec<PathBuf>,
+    /// The options the splitter was built with, recorded in the manifest
+    options: Options,
+    /// The seed used for randomisation
+    seed: u64,
     /// The desired splits
     splits: Splits,
     /// The stateful random number generator.
     rng: ChaChaRng,
-    /// The prefix for the output file(s)
-    output_prefix: Option<PathBuf>,
+    /// Where the output file(s) are written
+    layout: Arc<OutputLayout>,
+    /// The files written so far, which are removed if the run fails
+    files: Arc<OutputFiles>,
     /// The maximum size of each chunk
     chunk_size: Option<u64>,
+    /// The maximum size of each chunk in bytes
+    chunk_bytes: Option<ByteLimit>,
+    /// The exact number of chunks per split
+    shards: Option<NonZeroU64>,
     /// The total number of rows
     total_rows: Option<u64>,
+    /// Should each chunk get a consecutive run of rows?
+    contiguous: bool,
     /// Compression for input files
     input_compression: Compression,
-    /// Compression for output files
-    output_compression: Compression,
-    /// Is the input CSV?
-    csv: bool,
+    /// Compresses output files, if they should be gzip compressed
+    gzip: Option<Arc<GzipCompressor>>,
+    /// How to shuffle the rows of each output, if they should be shuffled
+    shuffle: Option<ShuffleSettings>,
+    /// How the input is divided into records
+    format: RecordFormat,
     /// Does the input have headers?
     ///
     /// Note: defaults to true.
@@ -158,7 +519,20 @@ pub struct Splitter {
 }
 
 impl Splitter {
-    pub fn run(mut self) -> Result<()> {
+    /// Split the input, writing every output.
+    ///
+    /// If anything goes wrong, any outputs already written are removed.
+    pub fn run(self) -> Result<()> {
+        let files = Arc::clone(&self.files);
+        let result = self.write();
+        if result.is_err() {
+            info!("Removing outputs after error");
+            files.remove_all();
+        }
+        result
+    }
+
+    fn write(mut self) -> Result<()> {
         let multi = MultiProgress::new();
 
         // Use a slightly different progress bar depending on the situation
@@ -210,158 +584,147 @@ impl Splitter {
                 .collect()
         };
 
-        let mut senders = HashMap::new();
-        let mut chunk_writers = Vec::new();
-        let output_path = match self.output_prefix {
-            Some(ref f) => f.clone(),
-            None => self.input.clone(),
+        let splits: Vec<SplitEnum> = match &self.splits {
+            Splits::Proportions(p) => p.iter().map(|s| SplitEnum::Proportion(s.clone())).collect(),
+            Splits::Rows(r) => r.iter().map(|s| SplitEnum::Rows(s.clone())).collect(),
         };
-        match &self.splits {
-            Splits::Proportions(p) => {
-                for split in p.iter() {
-                    let split = SplitEnum::Proportion((*split).clone());
-                    let (split_sender, mut split_chunk_writers) = SplitWriter::new(
-                        &output_path,
-                        &split,
-                        self.chunk_size,
-                        self.total_rows,
-                        self.output_compression,
-                    )?;
-                    senders.insert(split.name().to_string(), split_sender);
-                    chunk_writers.append(&mut split_chunk_writers);
-                }
-            }
-            Splits::Rows(r) => {
-                for split in r.iter() {
-                    let split = SplitEnum::Rows((*split).clone());
-                    let (split_sender, mut split_chunk_writers) = SplitWriter::new(
-                        &output_path,
-                        &split,
-                        self.chunk_size,
-                        self.total_rows,
-                        self.output_compression,
-                    )?;
-                    senders.insert(split.name().to_string(), split_sender);
-                    chunk_writers.append(&mut split_chunk_writers);
-                }
-            }
+        let plans = splits
+            .iter()
+            .map(|split| {
+                ChunkPlan::new(
+                    split,
+                    self.chunk_size,
+                    self.chunk_bytes,
+                    self.shards.map(NonZeroU64::get),
+                    self.total_rows,
+                    self.contiguous,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // Make sure no two chunks will be written to the same file before
+        // writing anything.
+        let paths = self
+            .layout
+            .validate(splits.iter().zip(&plans).flat_map(|(split, plan)| {
+                plan.initial_chunks()
+                    .into_iter()
+                    .map(move |(chunk, chunks)| (split.name(), chunk, chunks))
+            }))?;
+        // Refuse to overwrite the outputs of a previous run.
+        let indexes = match self.gzip.as_deref().map(GzipCompressor::format) {
+            Some(GzipFormat::Bgzf) => paths.iter().map(index_path).collect(),
+            _ => Vec::new(),
         };
+        self.files.check_new(
+            paths
+                .into_iter()
+                .chain(indexes)
+                .chain([self.layout.manifest_path(), self.layout.checksums_path()]),
+        )?;
 
-        let pool = rayon::ThreadPoolBuilder::new()
-            .num_threads(chunk_writers.len() + 2)
-            .thread_name(|num| format!("thread-{num}"))
-            .start_handler(|num| debug!("thread {} starting", num))
-            .exit_handler(|num| debug!("thread {} finishing", num))
-            .build()
-            .unwrap();
-
-        pool.scope(move |scope| {
-            info!("Reading data from {}", self.input.to_str().unwrap());
-            let reader_builder = if self.csv {
-                let mut reader_builder = csv::ReaderBuilder::new();
-                reader_builder.has_headers(false);
-                Some(reader_builder)
-            } else {
-                None
-            };
-            let mut reader = open_data(&self.input, self.input_compression, reader_builder)?;
-
-            if self.has_header {
-                info!("Writing header to files");
-                let header = match reader.read_line() {
-                    Some(h) => h?,
-                    None => return Err(Error::EmptyFile),
-                };
-                for sender in senders.values_mut() {
-                    sender.send_all(&header)?;
-                }
-            }
+        info!("Reading data from {}", self.inputs[0].display());
+        let mut reader = MultiReader::new(
+            self.inputs.clone(),
+            self.input_compression,
+            self.format.clone(),
+            self.has_header,
+        )?;
 
-            let has_header = self.has_header;
-            {
-                for writer in chunk_writers {
-                    scope.spawn(move |_| {
-                        // In most cases each writer will only deal with
-                        // one chunk. But if we're only told a proportion and
-                        // a chunk size (and no total rows), we'll be writing
-                        // to two files at once, and we'll need to switch to a
-                        // new file if we go over the chunk size.
-                        let mut chunk_id = writer.chunk_id;
-                        let mut rows_sent_to_chunk = 0;
-                        let mut file = writer.output(chunk_id).expect("Could not open file");
-                        let mut header: Header<String> = if has_header {
-                            Header::None
-                        } else {
-                            Header::Disabled
-                        };
-                        for row in writer.receiver.iter() {
-                            if header == Header::None {
-                                header = Header::Some(row.clone());
-                            }
-                            if let Some(chunk_size) = writer.chunk_size {
-                                if rows_sent_to_chunk > (chunk_size) {
-                                    // add one for header
-                                    // This should only ever happen if we weren't
-                                    // able to pre-calculate how many chunks were
-                                    // needed
-                                    chunk_id = chunk_id.map(|c| c + 2);
-                                    file = writer.output(chunk_id).expect("Could not open file");
-                                    if let Header::Some(h) = header.as_ref() {
-                                        writer
-                                            .handle_row(&mut file, h)
-                                            .expect("Could not write row to file");
-                                    }
-                                    rows_sent_to_chunk = 1
-                                }
-                            }
-                            writer
-                                .handle_row(&mut file, &row)
-                                .expect("Could not write row to file");
-                            rows_sent_to_chunk += 1;
-                        }
-                    })
-                }
+        let header = if self.has_header {
+            info!("Writing header to files");
+            match reader.read_line() {
+                Some(h) => Some(h?),
+                None => return Err(Error::EmptyFile),
             }
+        } else {
+            None
+        };
+
+        // The shuffle memory is shared by every split, and then by the chunks
+        // of each one.
+        let shuffle = self.shuffle.as_ref().map(|s| s.share(splits.len() as u64));
+        let mut senders = HashMap::new();
+        for (split, plan) in splits.iter().zip(plans) {
+            let writer = SplitWriter::new(
+                &self.layout,
+                &self.files,
+                split,
+                plan,
+                self.chunk_size,
+                self.chunk_bytes,
+                self.gzip.as_ref(),
+                shuffle.as_ref(),
+                header.clone(),
+            )?;
+            senders.insert(split.name().to_string(), writer);
+        }
 
-            info!("Reading lines");
-            while let Some(record) = reader.read_line() {
-                let split = self.splits.get_split(&mut self.rng);
-                match split {
-                    SplitSelection::Some(split) => {
-                        match senders.get_mut(split).unwrap().send(record.unwrap()) {
-                            Ok(_) => progress[split].inc(1),
-                            Err(e) => return Err(e),
-                        }
-                    }
-                    SplitSelection::None => continue,
-                    SplitSelection::Done => break,
+        info!("Reading lines");
+        let result = self.split_rows(&mut reader, &mut senders, &progress);
+        progress.values().for_each(|f| f.finish());
+
+        // Always wait for the writers to finish, even if reading failed.
+        let mut finished = Ok(Vec::new());
+        for split in &splits {
+            let sender = senders.remove(split.name()).unwrap();
+            finished = match (finished, sender.finish()) {
+                (Ok(mut summaries), Ok(summary)) => {
+                    summaries.push(summary);
+                    Ok(summaries)
                 }
-            }
-            progress.values().for_each(|f| f.finish());
-            info!("Finished writing to files");
+                (Err(e), _) | (_, Err(e)) => Err(e),
+            };
+        }
+        result?;
+        let split_summaries = finished?;
+        info!("Finished writing to files");
 
-            for (_, sender) in senders {
-                sender.finish();
-            }
-            Ok(())
-        })?;
-        Ok(())
-    }
-}
+        let inputs = reader
+            .checksums()?
+            .into_iter()
+            .map(|(path, bytes, sha256)| InputSummary {
+                path,
+                bytes,
+                sha256,
+            })
+            .collect();
+        let manifest = Manifest {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            seed: self.seed,
+            options: self.options,
+            inputs,
+            splits: split_summaries,
+        };
+        let manifest_path = self.layout.manifest_path();
+        info!("Writing manifest to {}", manifest_path.display());
+        manifest.write(self.files.create(&manifest_path)?)?;
+        let checksums_path = self.layout.checksums_path();
+        manifest.write_checksums(self.files.create(&checksums_path)?)?;
 
-#[derive(Debug, PartialEq)]
-enum Header<T> {
-    None,
-    Some(T),
-    Disabled,
-}
+        // Every output is complete, so move them all into place together,
+        // the manifest and checksums last.
+        self.files.commit_all()
+    }
 
-impl Header<String> {
-    fn as_ref(&self) -> Header<&str> {
-        match self {
-            Header::None => Header::None,
-            Header::Disabled => Header::Disabled,
-            Header::Some(s) => Header::Some(s.as_str()),
+    /// Assign each row read to a split, and send it to that split's writer.
+    fn split_rows(
+        &mut self,
+        reader: &mut impl LineReader,
+        senders: &mut HashMap<String, SplitWriter>,
+        progress: &HashMap<String, ProgressBar>,
+    ) -> Result<()> {
+        while let Some(record) = reader.read_line() {
+            let split = self.splits.get_split(&mut self.rng);
+            match split {
+                SplitSelection::Some(split) => {
+                    senders.get_mut(split).unwrap().send(record?)?;
+                    progress[split].inc(1);
+                }
+                SplitSelection::None => continue,
+                SplitSelection::Done => break,
+            }
         }
+        Ok(())
     }
 }
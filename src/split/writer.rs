@@ -1,46 +1,99 @@
+use std::collections::VecDeque;
 use std::fs::create_dir_all;
 use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::mpsc::{Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
 
+use super::manifest::{ChunkSummary, SplitSummary};
+use super::output::{OutputFiles, OutputLayout};
 use super::single::SplitEnum;
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::io;
+use crate::shuffle::{ExternalShuffle, ShuffleSettings};
 
-/// Accepts rows assigned to a split and writes them in an appropriate way.
-///
-/// If a max chunk size as been specified it will round-robin the rows between
-/// the splits.
-pub(crate) struct SplitWriter {
-    /// Sending halves of channels.
-    ///
-    /// We use a SyncSender here because we may end up reading much faster
-    /// than writing, and we need to limit the size of the buffers.
-    chunk_senders: Vec<SyncSender<String>>,
+/// A limit on the size of each chunk in bytes.
+#[derive(Clone, Copy, Debug)]
+pub struct ByteLimit {
+    /// The maximum number of bytes per chunk.
+    pub bytes: u64,
+    /// Whether the limit applies to the compressed size of each chunk rather
+    /// than the uncompressed size.
+    pub compressed: bool,
+}
 
-    /// Index of the chunk_sender which should receive the next row.
-    next_index: usize,
+/// The maximum number of full chunks which may still be writing while rows
+/// are sent to the next one, when rolling over to new chunks.
+const MAX_PENDING_CHUNKS: usize = 4;
+
+/// How the rows of a split are divided between chunks.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum ChunkPlan {
+    /// A fixed number of chunks, which rows are round-robined between.
+    ///
+    /// `exact` is false if the number of chunks is only an estimate.
+    Fixed { n_chunks: u64, exact: bool },
+    /// Chunks are opened one after another, as each one fills up, so each
+    /// chunk gets a consecutive run of the split's rows.
+    ///
+    /// `n_chunks` is the number of chunks, if known exactly in advance.
+    Rolling { n_chunks: Option<u64> },
+    /// A fixed number of chunks, each given a consecutive run of rows, with
+    /// sizes differing by at most one.
+    ///
+    /// `rows` is the number of rows in the split, which may be an estimate;
+    /// the last chunk takes any extra rows.
+    Balanced { n_chunks: u64, rows: u64 },
 }
 
-impl SplitWriter {
+impl ChunkPlan {
     pub fn new(
-        path: &Path,
         split: &SplitEnum,
         chunk_size: Option<u64>,
+        chunk_bytes: Option<ByteLimit>,
+        shards: Option<u64>,
         total_rows: Option<u64>,
-        compression: io::Compression,
-    ) -> Result<(Self, Vec<ChunkWriter>)> {
+        contiguous: bool,
+    ) -> Result<Self> {
+        if let Some(n_shards) = shards {
+            let rows = match (split, total_rows) {
+                _ if !contiguous || n_shards == 1 => None,
+                (SplitEnum::Rows(r), _) => Some(r.total as u64),
+                (SplitEnum::Proportion(p), Some(t)) => {
+                    Some((t as f64 * p.proportion).round() as u64)
+                }
+                (SplitEnum::Proportion(_), None) => return Err(Error::ShardsRequireTotalRows),
+            };
+            return Ok(match rows {
+                // Round-robin the rows so that shard sizes differ by at most
+                // one, without needing to know how many rows there are.
+                None => ChunkPlan::Fixed {
+                    n_chunks: n_shards,
+                    exact: true,
+                },
+                Some(rows) => ChunkPlan::Balanced {
+                    n_chunks: n_shards,
+                    rows,
+                },
+            });
+        }
         let n_chunks = match (split, chunk_size, total_rows) {
+            // We can't know in advance how many rows will fit in each chunk.
+            _ if chunk_bytes.is_some() => return Ok(ChunkPlan::Rolling { n_chunks: None }),
+
             // Just use one sender since there is no chunking required.
             (_, None, _) => 1,
 
             // Create one sender per chunk.
             (SplitEnum::Rows(r), Some(c), _) => (r.total / c as f64).ceil() as u64,
 
-            // TODO:
-            // We don't know how many chunks will be required. Create two
-            // chunks; we'll fix this later.
-            (SplitEnum::Proportion(_), Some(_), None) => 2,
+            // We don't know how many chunks will be required, so open each one
+            // once the previous one is full.
+            (SplitEnum::Proportion(_), Some(_), None) => {
+                return Ok(ChunkPlan::Rolling { n_chunks: None })
+            }
 
             // Use as many senders as we estimate there will be chunks for this
             // split.
@@ -48,126 +101,453 @@ impl SplitWriter {
                 ((t as f64) * p.proportion / c as f64).ceil() as u64 + 1
             }
         };
+        // We only know exactly how many chunks there will be if we know
+        // exactly how many rows there will be.
+        let exact = matches!((split, chunk_size), (_, None) | (SplitEnum::Rows(_), _));
+        if contiguous && n_chunks > 1 {
+            return Ok(ChunkPlan::Rolling {
+                n_chunks: exact.then_some(n_chunks),
+            });
+        }
+        Ok(ChunkPlan::Fixed { n_chunks, exact })
+    }
+
+    /// The most chunks which may be being written at once: every fixed chunk,
+    /// or the current chunk and those still finishing behind it.
+    pub fn max_live_chunks(&self) -> u64 {
+        match *self {
+            ChunkPlan::Fixed { n_chunks, .. } => n_chunks,
+            ChunkPlan::Rolling { .. } | ChunkPlan::Balanced { .. } => 1 + MAX_PENDING_CHUNKS as u64,
+        }
+    }
 
-        let mut chunk_senders = Vec::new();
-        let mut chunk_writers = Vec::new();
-        for chunk_id in 0..n_chunks {
-            let (sender, receiver) = std::sync::mpsc::sync_channel(100);
-            chunk_senders.push(sender);
-
-            let chunk_id = if n_chunks == 1 { None } else { Some(chunk_id) };
-            let chunk_writer = ChunkWriter::new(
-                path.to_path_buf(),
-                split.name().to_string(),
-                compression,
-                chunk_id,
-                chunk_size,
-                receiver,
-            );
-            chunk_writers.push(chunk_writer);
-        }
-
-        Ok((
-            SplitWriter {
-                chunk_senders,
-                next_index: 0,
-            },
-            chunk_writers,
-        ))
+    /// The chunks which will be written to initially, as pairs of chunk ID and
+    /// total number of chunks (if known).
+    pub fn initial_chunks(&self) -> Vec<(Option<u64>, Option<u64>)> {
+        match *self {
+            ChunkPlan::Fixed { n_chunks: 1, .. } => vec![(None, Some(1))],
+            ChunkPlan::Fixed { n_chunks, exact } => (0..n_chunks)
+                .map(|c| (Some(c), exact.then_some(n_chunks)))
+                .collect(),
+            ChunkPlan::Rolling {
+                n_chunks: Some(n_chunks),
+            }
+            | ChunkPlan::Balanced { n_chunks, .. } => {
+                (0..n_chunks).map(|c| (Some(c), Some(n_chunks))).collect()
+            }
+            // Check the first couple of chunks to make sure they're distinct.
+            ChunkPlan::Rolling { n_chunks: None } => vec![(Some(0), None), (Some(1), None)],
+        }
+    }
+}
+
+/// A chunk writer running on its own thread.
+struct OpenChunk {
+    /// Sending half of the chunk's channel.
+    ///
+    /// We use a SyncSender here because we may end up reading much faster
+    /// than writing, and we need to limit the size of the buffers.
+    sender: SyncSender<String>,
+    /// The number of rows sent to this chunk, including any header.
+    rows: u64,
+    /// The number of uncompressed bytes sent to this chunk.
+    bytes: u64,
+    /// The number of bytes written to the chunk's file so far.
+    bytes_written: Arc<AtomicU64>,
+    handle: JoinHandle<Result<Vec<ChunkSummary>>>,
+}
+
+/// Accepts rows assigned to a split and writes them in an appropriate way.
+///
+/// If a fixed number of chunks is needed it will round-robin the rows between
+/// them; otherwise it will open a new chunk whenever the current one is full.
+pub(crate) struct SplitWriter {
+    name: String,
+    layout: Arc<OutputLayout>,
+    files: Arc<OutputFiles>,
+    gzip: Option<Arc<io::GzipCompressor>>,
+    shuffle: Option<ShuffleSettings>,
+    plan: ChunkPlan,
+    chunk_size: Option<u64>,
+    chunk_bytes: Option<ByteLimit>,
+    /// The header, which is written at the start of every chunk.
+    header: Option<String>,
+
+    /// The chunks currently being written to.
+    chunks: Vec<OpenChunk>,
+    /// Chunks which have been closed but may still be writing, oldest first.
+    closed: VecDeque<JoinHandle<Result<Vec<ChunkSummary>>>>,
+    /// Summaries of chunks which have finished writing.
+    finished: Vec<ChunkSummary>,
+
+    /// Index of the chunk which should receive the next row.
+    next_index: usize,
+    /// The ID of the next chunk to open, when rolling over to new chunks.
+    next_chunk_id: u64,
+}
+
+impl SplitWriter {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        layout: &Arc<OutputLayout>,
+        files: &Arc<OutputFiles>,
+        split: &SplitEnum,
+        plan: ChunkPlan,
+        chunk_size: Option<u64>,
+        chunk_bytes: Option<ByteLimit>,
+        gzip: Option<&Arc<io::GzipCompressor>>,
+        shuffle: Option<&ShuffleSettings>,
+        header: Option<String>,
+    ) -> Result<Self> {
+        let mut writer = SplitWriter {
+            name: split.name().to_string(),
+            layout: Arc::clone(layout),
+            files: Arc::clone(files),
+            gzip: gzip.cloned(),
+            // Every chunk has its own shuffler, so share the memory between
+            // all those which may be live at once.
+            shuffle: shuffle.map(|s| s.share(plan.max_live_chunks())),
+            plan,
+            chunk_size,
+            chunk_bytes,
+            header,
+            chunks: Vec::new(),
+            closed: VecDeque::new(),
+            finished: Vec::new(),
+            next_index: 0,
+            next_chunk_id: 0,
+        };
+        match plan {
+            ChunkPlan::Fixed { .. } => {
+                for (chunk_id, chunks) in plan.initial_chunks() {
+                    // If the number of chunks is only an estimate, each chunk
+                    // may need to move on to a new file when it fills up.
+                    let chunk_size = if chunks.is_none() { chunk_size } else { None };
+                    let chunk = writer.open_chunk(chunk_id, chunks, chunk_size)?;
+                    writer.chunks.push(chunk);
+                }
+            }
+            ChunkPlan::Rolling { .. } | ChunkPlan::Balanced { .. } => {
+                let chunk = writer.open_chunk(Some(0), writer.n_chunks(), None)?;
+                writer.chunks.push(chunk);
+                writer.next_chunk_id = 1;
+            }
+        }
+        Ok(writer)
+    }
+
+    /// Start a thread writing to a new chunk.
+    fn open_chunk(
+        &self,
+        chunk_id: Option<u64>,
+        chunks: Option<u64>,
+        chunk_size: Option<u64>,
+    ) -> Result<OpenChunk> {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(100);
+        let bytes_written = Arc::new(AtomicU64::new(0));
+        let chunk_step = match self.plan {
+            ChunkPlan::Fixed { n_chunks, .. } => n_chunks,
+            ChunkPlan::Rolling { .. } | ChunkPlan::Balanced { .. } => 1,
+        };
+        let writer = ChunkWriter {
+            layout: Arc::clone(&self.layout),
+            files: Arc::clone(&self.files),
+            name: self.name.clone(),
+            gzip: self.gzip.clone(),
+            shuffle: self.shuffle.clone(),
+            chunk_id,
+            chunks,
+            chunk_size,
+            chunk_step,
+            header: self.header.clone(),
+            bytes_written: Arc::clone(&bytes_written),
+            receiver,
+        };
+        let handle = std::thread::Builder::new()
+            .name(format!("{}-{}", self.name, chunk_id.unwrap_or(0)))
+            .spawn(move || writer.run())?;
+        let header_bytes = self.header.as_ref().map(|h| h.len() as u64).unwrap_or(0);
+        Ok(OpenChunk {
+            sender,
+            rows: self.header.is_some() as u64,
+            bytes: header_bytes,
+            bytes_written,
+            handle,
+        })
+    }
+
+    /// The number of chunks in the split, if known in advance.
+    fn n_chunks(&self) -> Option<u64> {
+        match self.plan {
+            ChunkPlan::Fixed { n_chunks, exact } => exact.then_some(n_chunks),
+            ChunkPlan::Rolling { n_chunks } => n_chunks,
+            ChunkPlan::Balanced { n_chunks, .. } => Some(n_chunks),
+        }
+    }
+
+    /// The maximum number of rows to send to the current chunk.
+    fn row_limit(&self) -> Option<u64> {
+        match self.plan {
+            ChunkPlan::Balanced { n_chunks, rows } => {
+                let chunk_id = self.next_chunk_id - 1;
+                if chunk_id + 1 >= n_chunks {
+                    // The last chunk takes any rows beyond the estimate.
+                    None
+                } else {
+                    Some(rows / n_chunks + (chunk_id < rows % n_chunks) as u64)
+                }
+            }
+            _ => self.chunk_size,
+        }
+    }
+
+    /// Whether the chunk is full, and we should move on to the next one
+    /// rather than send it `row`.
+    fn is_full(&self, chunk: &OpenChunk, row: &str) -> bool {
+        let header_rows = self.header.is_some() as u64;
+        if chunk.rows <= header_rows {
+            // Always write at least one row to each chunk.
+            return false;
+        }
+        if let Some(row_limit) = self.row_limit() {
+            if chunk.rows - header_rows >= row_limit {
+                return true;
+            }
+        }
+        match self.chunk_bytes {
+            Some(ByteLimit {
+                bytes,
+                compressed: false,
+            }) => chunk.bytes + row.len() as u64 > bytes,
+            Some(ByteLimit {
+                bytes,
+                compressed: true,
+            }) => chunk.bytes_written.load(Ordering::Relaxed) >= bytes,
+            None => false,
+        }
     }
 
     /// Send a row to this split.
     ///
     /// The sender will assign it to the correct chunk (if there was no maximum
     /// chunk size specified, there is effectively only one chunk!)
-    /// This will round-robin through the chunks.
     pub fn send(&mut self, row: String) -> Result<bool> {
-        match self.chunk_senders.get(self.next_index) {
-            Some(sender) => {
-                sender.send(row)?;
-                self.next_index += 1;
-            }
-            None => {
-                // Start again at the next chunk
-                self.chunk_senders[0].send(row)?;
-                self.next_index = 1;
+        if let ChunkPlan::Rolling { .. } | ChunkPlan::Balanced { .. } = self.plan {
+            if self.is_full(&self.chunks[0], &row) {
+                self.next_chunk()?;
             }
         }
+        // This will round-robin through the chunks.
+        if self.next_index >= self.chunks.len() {
+            self.next_index = 0;
+        }
+        let chunk = &mut self.chunks[self.next_index];
+        chunk.rows += 1;
+        chunk.bytes += row.len() as u64;
+        if chunk.sender.send(row).is_err() {
+            // The chunk's thread has stopped, which means it hit an error.
+            let chunk = self.chunks.remove(self.next_index);
+            return Err(join_chunk(chunk.handle)
+                .err()
+                .unwrap_or(Error::WriterStopped));
+        }
+        self.next_index += 1;
         Ok(true)
     }
 
-    /// Send a row to all splits.
-    ///
-    /// Used for the header row.
-    pub fn send_all(&mut self, row: &str) -> Result<()> {
-        for sender in &self.chunk_senders {
-            sender.send(row.to_string())?
+    /// Close the current chunk and move on to the next one.
+    fn next_chunk(&mut self) -> Result<()> {
+        let chunk = self.open_chunk(Some(self.next_chunk_id), self.n_chunks(), None)?;
+        self.next_chunk_id += 1;
+        let full = std::mem::replace(&mut self.chunks[0], chunk);
+        // Dropping the sender lets the chunk's thread finish writing while we
+        // carry on with the next chunk.
+        self.closed.push_back(full.handle);
+        if self.closed.len() > MAX_PENDING_CHUNKS {
+            // Don't let too many chunks pile up if writing is slow.
+            let summaries = join_chunk(self.closed.pop_front().unwrap())?;
+            self.finished.extend(summaries);
         }
         Ok(())
     }
 
-    pub fn finish(self) {
-        for sender in self.chunk_senders {
-            drop(sender);
+    /// Finish writing all chunks, waiting for them to be flushed, and
+    /// summarise what was written.
+    pub fn finish(mut self) -> Result<SplitSummary> {
+        if let ChunkPlan::Rolling {
+            n_chunks: Some(n_chunks),
         }
+        | ChunkPlan::Balanced { n_chunks, .. } = self.plan
+        {
+            // Write every chunk that `{chunks}` promised, even if there were
+            // fewer rows than expected.
+            while self.next_chunk_id < n_chunks {
+                self.next_chunk()?;
+            }
+        }
+        let mut handles = self.closed;
+        for chunk in self.chunks {
+            drop(chunk.sender);
+            handles.push_back(chunk.handle);
+        }
+        let mut result = Ok(());
+        let mut summaries = self.finished;
+        for handle in handles {
+            match join_chunk(handle) {
+                Ok(s) => summaries.extend(s),
+                Err(e) => result = result.and(Err(e)),
+            }
+        }
+        result.map(|_| SplitSummary::new(self.name, summaries))
     }
 }
 
+/// Wait for a chunk's thread to finish, returning any error it hit.
+fn join_chunk(handle: JoinHandle<Result<Vec<ChunkSummary>>>) -> Result<Vec<ChunkSummary>> {
+    handle.join().unwrap_or(Err(Error::WriterPanicked))
+}
+
 /// Writes rows to files once they've been assigned to a split.
 pub struct ChunkWriter {
-    path: PathBuf,
+    layout: Arc<OutputLayout>,
+    files: Arc<OutputFiles>,
     name: String,
-    compression: io::Compression,
-    pub chunk_id: Option<u64>,
-    pub chunk_size: Option<u64>,
-    pub receiver: Receiver<String>,
+    /// Compresses the output, if it should be gzip compressed.
+    gzip: Option<Arc<io::GzipCompressor>>,
+    /// How to shuffle the rows of each file, if they should be shuffled.
+    shuffle: Option<ShuffleSettings>,
+    chunk_id: Option<u64>,
+    /// The number of chunks in the split, if known in advance.
+    chunks: Option<u64>,
+    /// The number of rows after which to move on to a new file, if the number
+    /// of chunks needed wasn't known in advance.
+    chunk_size: Option<u64>,
+    /// How much to increase the chunk ID by when moving on to a new file.
+    chunk_step: u64,
+    header: Option<String>,
+    /// The number of bytes written to the current file.
+    bytes_written: Arc<AtomicU64>,
+    receiver: Receiver<String>,
+}
+
+/// A file being written by a chunk writer.
+pub struct ChunkOutput {
+    path: PathBuf,
+    /// Where the file is written until it's complete.
+    temp: PathBuf,
+    checksum: io::SharedChecksum,
+    file: io::OutputWriter,
+    /// Where rows start, if the file is BGZF and needs a row index.
+    index: Option<io::RowIndexWriter>,
+    /// Rows waiting to be written in shuffled order, if they're shuffled.
+    shuffle: Option<ExternalShuffle>,
 }
 
 impl ChunkWriter {
-    fn new(
-        path: PathBuf,
-        name: String,
-        compression: io::Compression,
-        chunk_id: Option<u64>,
-        chunk_size: Option<u64>,
-        receiver: Receiver<String>,
-    ) -> Self {
-        ChunkWriter {
-            path,
-            name,
-            compression,
-            chunk_id,
-            chunk_size,
-            receiver,
+    pub fn output(&self, chunk_id: Option<u64>) -> Result<ChunkOutput> {
+        let filename = self.layout.path(&self.name, chunk_id, self.chunks)?;
+        if let Some(dir) = filename.parent() {
+            create_dir_all(dir)?;
         }
+        self.bytes_written.store(0, Ordering::Relaxed);
+        let checksum = io::SharedChecksum::default();
+        let temp = self.files.create(&filename)?;
+        let file = io::open_output(&temp, self.gzip.as_deref(), &self.bytes_written, &checksum)?;
+        let bgzf = self.gzip.as_ref().map(|g| g.format()) == Some(io::GzipFormat::Bgzf);
+        let mut output = ChunkOutput {
+            path: filename,
+            temp,
+            checksum,
+            file,
+            index: bgzf.then(io::RowIndexWriter::default),
+            shuffle: self
+                .shuffle
+                .as_ref()
+                .map(|s| s.shuffler(&self.name, chunk_id)),
+        };
+        if let Some(header) = &self.header {
+            output.file.write_all(header.as_bytes())?;
+            if let Some(index) = &mut output.index {
+                index.header(header.len());
+            }
+        }
+        Ok(output)
     }
 
-    pub fn output(&self, chunk_id: Option<u64>) -> Result<io::OutputWriter> {
-        let mut filename = self.path.clone();
-        let original_filename = self.path.file_stem().unwrap();
-        filename.pop();
-        filename.push(&self.name);
-        create_dir_all(&filename)?;
-        let chunk_part = match chunk_id {
-            None => "".to_string(),
-            Some(c) => format!(".{c:0>4}"),
-        };
-        let extension = match self.compression {
-            io::Compression::GzipCompression => ".gz",
-            io::Compression::Uncompressed => "",
+    /// Finish writing a file, along with any row index, and summarise what
+    /// was written to it. The file is left under its temporary name until the
+    /// whole run has succeeded.
+    fn close(
+        &self,
+        chunk_id: Option<u64>,
+        mut output: ChunkOutput,
+        rows: u64,
+    ) -> Result<ChunkSummary> {
+        if let Some(shuffle) = output.shuffle.take() {
+            shuffle.finish(|row| write_row(&mut output.file, &mut output.index, &row))?;
+        }
+        output.file.finish()?;
+        let index = match output.index {
+            Some(index) => {
+                let path = io::index_path(&output.path);
+                index.write(&output.temp, self.files.create(&path)?)?;
+                Some(path)
+            }
+            None => None,
         };
-        filename.push(format!(
-            "{}.{}{}.csv{}",
-            original_filename.to_string_lossy(),
-            &self.name,
-            chunk_part,
-            extension,
-        ));
-        io::open_output(filename, self.compression)
+        let (bytes, sha256) = output.checksum.lock().unwrap().digest();
+        Ok(ChunkSummary {
+            chunk: chunk_id,
+            path: self.layout.relative_path(&output.path),
+            index: index.map(|i| self.layout.relative_path(&i)),
+            rows,
+            bytes,
+            sha256,
+        })
+    }
+
+    /// Write every row received to the chunk's file(s).
+    fn run(self) -> Result<Vec<ChunkSummary>> {
+        let mut summaries = Vec::new();
+        let mut chunk_id = self.chunk_id;
+        let mut rows_sent_to_chunk = 0;
+        let mut output = self.output(chunk_id)?;
+        for row in self.receiver.iter() {
+            if let Some(chunk_size) = self.chunk_size {
+                if rows_sent_to_chunk >= chunk_size {
+                    // This should only ever happen if we weren't able to
+                    // pre-calculate how many chunks were needed.
+                    summaries.push(self.close(chunk_id, output, rows_sent_to_chunk)?);
+                    chunk_id = chunk_id.map(|c| c + self.chunk_step);
+                    output = self.output(chunk_id)?;
+                    rows_sent_to_chunk = 0;
+                }
+            }
+            self.handle_row(&mut output, row)?;
+            rows_sent_to_chunk += 1;
+        }
+        summaries.push(self.close(chunk_id, output, rows_sent_to_chunk)?);
+        Ok(summaries)
     }
+
     /// Handle writing of a row to this chunk.
-    pub fn handle_row(&self, file: &mut io::OutputWriter, row: &str) -> Result<()> {
-        file.write_all(row.as_bytes())?;
-        Ok(())
+    pub fn handle_row(&self, output: &mut ChunkOutput, row: String) -> Result<()> {
+        match &mut output.shuffle {
+            Some(shuffle) => shuffle.push(row),
+            None => write_row(&mut output.file, &mut output.index, &row),
+        }
+    }
+}
+
+/// Write a row to a file, recording where it starts in any row index.
+fn write_row(
+    file: &mut io::OutputWriter,
+    index: &mut Option<io::RowIndexWriter>,
+    row: &str,
+) -> Result<()> {
+    file.write_all(row.as_bytes())?;
+    if let Some(index) = index {
+        index.row(row.len());
     }
+    Ok(())
 }
@@ -0,0 +1,549 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::Path;
+
+use log::info;
+use serde::Serialize;
+
+use crate::error::Result;
+use crate::io::{open_data, Column, FieldReader, SharedChecksum};
+use crate::split::Manifest;
+
+/// The default largest difference between splits which isn't flagged.
+pub const DEFAULT_MAX_DIFFERENCE: f64 = 0.1;
+
+/// Summarise each split written by a previous run, so that they can be
+/// compared: the number of rows, the distribution of an optional label
+/// column, the missing values and numeric summary of each column, and a
+/// histogram of row lengths.
+///
+/// `path` is either a manifest or a directory containing one. Rows are split
+/// into columns if they're CSV or plain lines, and a field is missing if it's
+/// empty or absent. A column is numeric if every value which isn't missing is
+/// a finite number.
+///
+/// Statistics which differ between splits by more than `max_difference` are
+/// flagged. Proportions (of labels, missing values and row lengths) are
+/// compared by their absolute difference, and means by their difference in
+/// pooled standard deviations.
+pub fn stats<P: AsRef<Path>>(
+    path: P,
+    label: Option<&Column>,
+    max_difference: f64,
+) -> Result<StatsReport> {
+    let (manifest, dir) = Manifest::find(path)?;
+    let options = &manifest.options;
+    let has_header = options.outputs_have_header();
+    let mut format = options.format.clone();
+
+    let mut splits = Vec::with_capacity(manifest.splits.len());
+    let mut label_name = None;
+    for split in &manifest.splits {
+        info!("Reading split {}", split.name);
+        let mut stats = SplitAccumulator::default();
+        for chunk in &split.chunks {
+            let path = dir.join(&chunk.path);
+            let checksum = SharedChecksum::default();
+            let mut reader =
+                open_data(&path, options.output_compression(), &mut format, &checksum)?;
+            let fields = FieldReader::new(&format);
+            let mut label_index = None;
+            if has_header {
+                if let Some(header) = reader.read_line() {
+                    let header = header?;
+                    if let Some(fields) = &fields {
+                        stats.set_header(fields.fields(&header)?);
+                    }
+                }
+            }
+            if let Some(label) = label {
+                let index = label.index(stats.header.as_deref())?;
+                label_name = Some(match stats.header.as_ref().and_then(|h| h.get(index)) {
+                    Some(name) => name.clone(),
+                    None => label.to_string(),
+                });
+                label_index = Some(index);
+            }
+            while let Some(record) = reader.read_line() {
+                let record = record?;
+                let fields = match &fields {
+                    Some(fields) => Some(fields.fields(&record)?),
+                    None => None,
+                };
+                stats.add(&record, fields, label_index);
+            }
+        }
+        splits.push(stats.finish(split.name.clone()));
+    }
+
+    let mut report = StatsReport {
+        max_difference,
+        label: label_name,
+        splits,
+        differences: Vec::new(),
+    };
+    report.differences = report
+        .table()
+        .into_iter()
+        .filter_map(|row| {
+            row.difference.map(|difference| Difference {
+                statistic: row.statistic,
+                difference,
+                flagged: difference > max_difference,
+            })
+        })
+        .collect();
+    Ok(report)
+}
+
+/// Statistics for every split of a run, and how much they differ.
+#[derive(Debug, Serialize)]
+pub struct StatsReport {
+    /// The largest difference between splits which isn't flagged.
+    pub max_difference: f64,
+    /// The name of the label column, if one was given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub splits: Vec<SplitStats>,
+    /// The largest difference between any two splits of each statistic which
+    /// can be compared.
+    pub differences: Vec<Difference>,
+}
+
+impl StatsReport {
+    /// The statistics which differ by more than the maximum difference.
+    pub fn flagged(&self) -> impl Iterator<Item = &Difference> {
+        self.differences.iter().filter(|d| d.flagged)
+    }
+
+    /// Write the statistics as a table with a column for each split, and the
+    /// difference between them.
+    pub fn write_table<W: Write>(&self, mut out: W) -> Result<()> {
+        let rows = self.table();
+        let first_width = rows
+            .iter()
+            .map(|r| r.statistic.len())
+            .chain(["statistic".len()])
+            .max()
+            .unwrap_or(0);
+        let widths: Vec<usize> = self
+            .splits
+            .iter()
+            .enumerate()
+            .map(|(i, split)| {
+                rows.iter()
+                    .map(|r| r.values[i].len())
+                    .chain([split.name.len()])
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        write!(out, "{:first_width$}", "statistic")?;
+        for (split, width) in self.splits.iter().zip(&widths) {
+            write!(out, "  {:>width$}", split.name)?;
+        }
+        writeln!(out, "  difference")?;
+        for row in &rows {
+            write!(out, "{:first_width$}", row.statistic)?;
+            for (value, width) in row.values.iter().zip(&widths) {
+                write!(out, "  {:>width$}", value)?;
+            }
+            match row.difference {
+                Some(d) if d > self.max_difference => write!(out, "  {:>10.3} *", d)?,
+                Some(d) => write!(out, "  {:>10.3}", d)?,
+                None => {}
+            }
+            writeln!(out)?;
+        }
+
+        let flagged = self.flagged().count();
+        writeln!(out)?;
+        if flagged > 0 {
+            writeln!(
+                out,
+                "* {} statistic(s) differ by more than {} between splits",
+                flagged, self.max_difference
+            )?;
+        } else {
+            writeln!(
+                out,
+                "No statistics differ by more than {} between splits",
+                self.max_difference
+            )?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Every statistic, with its value for each split and the largest
+    /// difference between splits if it can be compared.
+    fn table(&self) -> Vec<TableRow> {
+        let mut rows = vec![TableRow::new(
+            "rows".to_string(),
+            self.splits.iter().map(|s| s.rows.to_string()).collect(),
+            None,
+        )];
+
+        if let Some(label) = &self.label {
+            let values: BTreeSet<&String> =
+                self.splits.iter().flat_map(|s| s.labels.keys()).collect();
+            for value in values {
+                let shares = self.shares(|s| s.labels.get(value).copied().unwrap_or(0));
+                rows.push(TableRow::proportions(
+                    format!("{}={}", label, value),
+                    &shares,
+                ));
+            }
+        }
+
+        let columns = self
+            .splits
+            .iter()
+            .map(|s| s.columns.len())
+            .max()
+            .unwrap_or(0);
+        for i in 0..columns {
+            let name = match self.splits.iter().find_map(|s| s.columns.get(i)) {
+                Some(column) => &column.name,
+                None => continue,
+            };
+            let shares = self.shares(|s| s.columns.get(i).map_or(s.rows, |c| c.missing));
+            rows.push(TableRow::proportions(format!("missing {}", name), &shares));
+
+            let numeric: Vec<Option<&NumericStats>> = self
+                .splits
+                .iter()
+                .map(|s| s.columns.get(i).and_then(|c| c.numeric.as_ref()))
+                .collect();
+            if numeric.iter().all(Option::is_none) {
+                continue;
+            }
+            let format = |f: fn(&NumericStats) -> f64| -> Vec<String> {
+                numeric
+                    .iter()
+                    .map(|n| n.map_or_else(String::new, |n| format!("{:.3}", f(n))))
+                    .collect()
+            };
+            let compared: Vec<&NumericStats> = numeric.iter().flatten().copied().collect();
+            let difference = match numeric.iter().all(Option::is_some) {
+                true => max_pairwise(&compared, standardised_mean_difference),
+                false => None,
+            };
+            rows.push(TableRow::new(
+                format!("mean {}", name),
+                format(|n| n.mean),
+                difference,
+            ));
+            rows.push(TableRow::new(
+                format!("sd {}", name),
+                format(|n| n.sd),
+                None,
+            ));
+            rows.push(TableRow::new(
+                format!("min {}", name),
+                format(|n| n.min),
+                None,
+            ));
+            rows.push(TableRow::new(
+                format!("max {}", name),
+                format(|n| n.max),
+                None,
+            ));
+        }
+
+        let buckets: BTreeSet<(u64, u64)> = self
+            .splits
+            .iter()
+            .flat_map(|s| s.lengths.iter().map(|b| (b.min, b.max)))
+            .collect();
+        for (min, max) in buckets {
+            let shares = self.shares(|s| {
+                s.lengths
+                    .iter()
+                    .find(|b| b.min == min)
+                    .map_or(0, |b| b.rows)
+            });
+            let statistic = match min == max {
+                true => format!("length {}", min),
+                false => format!("length {}-{}", min, max),
+            };
+            rows.push(TableRow::proportions(statistic, &shares));
+        }
+        rows
+    }
+
+    /// The proportion of each split's rows counted by `count`, or `None` for
+    /// an empty split.
+    fn shares<F: Fn(&SplitStats) -> u64>(&self, count: F) -> Vec<Option<f64>> {
+        self.splits
+            .iter()
+            .map(|s| match s.rows {
+                0 => None,
+                rows => Some(count(s) as f64 / rows as f64),
+            })
+            .collect()
+    }
+}
+
+/// One line of the comparison table.
+struct TableRow {
+    statistic: String,
+    values: Vec<String>,
+    difference: Option<f64>,
+}
+
+impl TableRow {
+    fn new(statistic: String, values: Vec<String>, difference: Option<f64>) -> Self {
+        TableRow {
+            statistic,
+            values,
+            difference,
+        }
+    }
+
+    /// A row of proportions, compared by their absolute difference. Empty
+    /// splits have no proportion and aren't compared.
+    fn proportions(statistic: String, shares: &[Option<f64>]) -> Self {
+        let values = shares
+            .iter()
+            .map(|s| s.map_or_else(String::new, |s| format!("{:.3}", s)))
+            .collect();
+        let compared: Vec<f64> = shares.iter().flatten().copied().collect();
+        let difference = max_pairwise(&compared, |a, b| (a - b).abs());
+        TableRow::new(statistic, values, difference)
+    }
+}
+
+/// The largest difference between any two values, or `None` if there are
+/// fewer than two.
+fn max_pairwise<T: Copy, F: Fn(T, T) -> f64>(values: &[T], difference: F) -> Option<f64> {
+    let mut max = None;
+    for (i, a) in values.iter().enumerate() {
+        for b in &values[i + 1..] {
+            let d = difference(*a, *b);
+            max = Some(max.map_or(d, |m: f64| m.max(d)));
+        }
+    }
+    max
+}
+
+/// The difference between two means, in pooled standard deviations.
+fn standardised_mean_difference(a: &NumericStats, b: &NumericStats) -> f64 {
+    let pooled = ((a.sd * a.sd + b.sd * b.sd) / 2.0).sqrt();
+    let difference = (a.mean - b.mean).abs();
+    if difference == 0.0 {
+        0.0
+    } else if pooled == 0.0 {
+        f64::INFINITY
+    } else {
+        difference / pooled
+    }
+}
+
+/// The statistics of a single split.
+#[derive(Debug, Serialize)]
+pub struct SplitStats {
+    pub name: String,
+    /// The number of rows, not including headers.
+    pub rows: u64,
+    /// The number of rows with each value of the label column.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, u64>,
+    pub columns: Vec<ColumnStats>,
+    /// The number of rows of each range of lengths in bytes, not including
+    /// line endings.
+    pub lengths: Vec<LengthBucket>,
+}
+
+/// The statistics of a single column of a split.
+#[derive(Debug, Serialize)]
+pub struct ColumnStats {
+    pub name: String,
+    /// The number of rows where the column is empty or absent.
+    pub missing: u64,
+    /// A summary of the values, if they're all numeric.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub numeric: Option<NumericStats>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NumericStats {
+    pub mean: f64,
+    /// The sample standard deviation.
+    pub sd: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+/// The number of rows with lengths from `min` to `max` bytes, inclusive.
+#[derive(Debug, Serialize)]
+pub struct LengthBucket {
+    pub min: u64,
+    pub max: u64,
+    pub rows: u64,
+}
+
+/// The largest difference between splits of one statistic.
+#[derive(Debug, Serialize)]
+pub struct Difference {
+    pub statistic: String,
+    pub difference: f64,
+    /// Whether the difference is more than the maximum.
+    pub flagged: bool,
+}
+
+/// Collects the statistics of a split as its rows are read.
+#[derive(Default)]
+struct SplitAccumulator {
+    header: Option<Vec<String>>,
+    rows: u64,
+    labels: BTreeMap<String, u64>,
+    columns: Vec<ColumnAccumulator>,
+    /// The number of rows in each bucket, numbered by the bit length of the
+    /// row lengths they hold.
+    lengths: BTreeMap<u32, u64>,
+}
+
+impl SplitAccumulator {
+    fn set_header(&mut self, header: Vec<String>) {
+        if self.header.is_none() {
+            self.columns.resize_with(header.len(), Default::default);
+            self.header = Some(header);
+        }
+    }
+
+    fn add(&mut self, record: &str, fields: Option<Vec<String>>, label: Option<usize>) {
+        let length = record.trim_end_matches(['\n', '\r']).len() as u64;
+        *self
+            .lengths
+            .entry(u64::BITS - length.leading_zeros())
+            .or_default() += 1;
+
+        if let Some(fields) = fields {
+            if let Some(label) = label {
+                let value = fields.get(label).cloned().unwrap_or_default();
+                *self.labels.entry(value).or_default() += 1;
+            }
+            if fields.len() > self.columns.len() {
+                // Earlier rows didn't have these columns at all.
+                let rows = self.rows;
+                self.columns
+                    .resize_with(fields.len(), || ColumnAccumulator {
+                        missing: rows,
+                        ..Default::default()
+                    });
+            }
+            for (i, column) in self.columns.iter_mut().enumerate() {
+                column.add(fields.get(i).map(String::as_str));
+            }
+        }
+        self.rows += 1;
+    }
+
+    fn finish(self, name: String) -> SplitStats {
+        let header = self.header.unwrap_or_default();
+        let columns = self
+            .columns
+            .into_iter()
+            .enumerate()
+            .map(|(i, column)| ColumnStats {
+                name: header
+                    .get(i)
+                    .cloned()
+                    .unwrap_or_else(|| (i + 1).to_string()),
+                missing: column.missing,
+                numeric: column.numeric(),
+            })
+            .collect();
+        let lengths = self
+            .lengths
+            .into_iter()
+            .map(|(bits, rows)| LengthBucket {
+                min: match bits {
+                    0 => 0,
+                    bits => 1 << (bits - 1),
+                },
+                max: (1u64 << bits) - 1,
+                rows,
+            })
+            .collect();
+        SplitStats {
+            name,
+            rows: self.rows,
+            labels: self.labels,
+            columns,
+            lengths,
+        }
+    }
+}
+
+/// Collects the statistics of a column, using Welford's algorithm for the
+/// mean and variance.
+struct ColumnAccumulator {
+    missing: u64,
+    /// Whether every value so far has been numeric.
+    numeric: bool,
+    count: u64,
+    mean: f64,
+    /// The sum of squared differences from the mean.
+    m2: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Default for ColumnAccumulator {
+    fn default() -> Self {
+        ColumnAccumulator {
+            missing: 0,
+            numeric: true,
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+}
+
+impl ColumnAccumulator {
+    fn add(&mut self, value: Option<&str>) {
+        let value = match value.map(str::trim) {
+            Some(value) if !value.is_empty() => value,
+            _ => {
+                self.missing += 1;
+                return;
+            }
+        };
+        if !self.numeric {
+            return;
+        }
+        match value.parse::<f64>() {
+            Ok(x) if x.is_finite() => {
+                self.count += 1;
+                let delta = x - self.mean;
+                self.mean += delta / self.count as f64;
+                self.m2 += delta * (x - self.mean);
+                self.min = self.min.min(x);
+                self.max = self.max.max(x);
+            }
+            _ => self.numeric = false,
+        }
+    }
+
+    fn numeric(&self) -> Option<NumericStats> {
+        if !self.numeric || self.count == 0 {
+            return None;
+        }
+        let variance = match self.count {
+            1 => 0.0,
+            n => self.m2 / (n - 1) as f64,
+        };
+        Some(NumericStats {
+            mean: self.mean,
+            sd: variance.sqrt(),
+            min: self.min,
+            max: self.max,
+        })
+    }
+}
@@ -0,0 +1,71 @@
+use std::io::Write;
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::io::{open_data, Checksum, SharedChecksum};
+use crate::split::{ChunkSummary, Manifest};
+
+/// Check that the outputs listed in a manifest are intact: that each file has
+/// the recorded size, checksum and number of rows, and that compressed files
+/// decompress cleanly.
+///
+/// `path` is either a manifest or a directory, in which case the outputs of
+/// every manifest in it are checked. The result of checking each file is
+/// written to `out`, and an error is returned if any failed.
+pub fn verify<P: AsRef<Path>, W: Write>(path: P, mut out: W) -> Result<()> {
+    let mut failures = 0;
+    for manifest_path in Manifest::paths(path)? {
+        let (manifest, dir) = Manifest::find(manifest_path)?;
+        for chunk in manifest.splits.iter().flat_map(|s| &s.chunks) {
+            let path = dir.join(&chunk.path);
+            match verify_chunk(&manifest, chunk, &path) {
+                Ok(()) => writeln!(out, "{}: OK", path.display())?,
+                Err(e) => {
+                    writeln!(out, "{}: FAILED ({})", path.display(), e)?;
+                    failures += 1;
+                }
+            }
+        }
+    }
+    if failures > 0 {
+        return Err(Error::VerificationFailed(failures));
+    }
+    Ok(())
+}
+
+/// Check a single output file against its summary in the manifest.
+fn verify_chunk(manifest: &Manifest, chunk: &ChunkSummary, path: &Path) -> Result<()> {
+    let options = &manifest.options;
+    let mut format = options.format.clone();
+    let has_header = options.outputs_have_header();
+
+    // Count the records while hashing the file, which also checks that any
+    // compressed data is complete.
+    let checksum = SharedChecksum::default();
+    let mut reader = open_data(path, options.output_compression(), &mut format, &checksum)?;
+    let mut records = 0;
+    while let Some(record) = reader.read_line() {
+        record?;
+        records += 1;
+    }
+    drop(reader);
+    let (bytes, sha256) = Checksum::finish(&checksum, path)?;
+
+    let rows = records - (has_header && records > 0) as u64;
+    let mismatch = if bytes != chunk.bytes {
+        Some(format!("expected {} bytes, found {}", chunk.bytes, bytes))
+    } else if sha256 != chunk.sha256 {
+        Some(format!(
+            "expected SHA-256 {}, found {}",
+            chunk.sha256, sha256
+        ))
+    } else if rows != chunk.rows {
+        Some(format!("expected {} rows, found {}", chunk.rows, rows))
+    } else {
+        None
+    };
+    match mismatch {
+        Some(mismatch) => Err(Error::OutputMismatch(mismatch)),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,47 @@
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::Command;
+
+use flate2::read::MultiGzDecoder;
+use ttv::{BgzfReader, RecordFormat};
+
+#[test]
+fn read_rows_from_bgzf_output() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("bgzf-read-rows");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    // Pad the rows so that the outputs span several BGZF blocks.
+    let mut data = String::from("id,value\n");
+    for i in 0..300u64 {
+        let value: String = (0..25)
+            .map(|j| format!("{:016x}", (i * 25 + j).wrapping_mul(0x9e37_79b9_7f4a_7c15)))
+            .collect();
+        data.push_str(&format!("{},{}\n", i, value));
+    }
+    fs::write(dir.join("data.csv"), data).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_ttv"))
+        .current_dir(&dir)
+        .args(["split", "data.csv", "--rows=train=250,test=50", "--seed=42"])
+        .arg("--bgzf")
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // Compare against the rows read by decompressing the whole file.
+    let path = dir.join("train/data.train.csv.gz");
+    let mut contents = String::new();
+    MultiGzDecoder::new(fs::File::open(&path).unwrap())
+        .read_to_string(&mut contents)
+        .unwrap();
+    let lines: Vec<String> = contents.split_inclusive('\n').map(String::from).collect();
+
+    let reader = BgzfReader::open(&path, RecordFormat::Lines).unwrap();
+    assert_eq!(reader.rows(), 250);
+    assert_eq!(reader.header().unwrap().as_deref(), Some("id,value\n"));
+    assert_eq!(reader.read_rows(0..3).unwrap(), lines[1..4]);
+    assert_eq!(reader.read_rows(100..140).unwrap(), lines[101..141]);
+    assert_eq!(reader.read_rows(240..260).unwrap(), lines[241..251]);
+    assert!(reader.read_rows(260..270).unwrap().is_empty());
+}
@@ -0,0 +1,84 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+/// Every file under `dir`, including hidden ones, relative to `root`.
+fn files(dir: &Path, root: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(self::files(&path, root));
+        } else {
+            files.push(path.strip_prefix(root).unwrap().to_path_buf());
+        }
+    }
+    files.sort();
+    files
+}
+
+#[test]
+fn failed_split_leaves_no_outputs() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("failed-split");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    // Many chunks are complete by the time the invalid last row is read.
+    let mut data = b"id,value\n".to_vec();
+    for i in 0..1000 {
+        data.extend(format!("{},{}\n", i, i * i).bytes());
+    }
+    data.extend(b"1000,\xff\xfe\n");
+    fs::write(dir.join("data.csv"), data).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ttv"))
+        .current_dir(&dir)
+        .args([
+            "split",
+            "data.csv",
+            "--prop=train=0.9,test=0.1",
+            "--seed=42",
+        ])
+        .args(["--chunk-size", "10"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+
+    assert_eq!(files(&dir, &dir), [PathBuf::from("data.csv")]);
+}
+
+#[test]
+fn killed_split_leaves_only_temporary_files() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("killed-split");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ttv"))
+        .current_dir(&dir)
+        .args(["split", "-", "--prop=train=0.9,test=0.1", "--seed=42"])
+        .args(["--chunk-size", "10", "--output-prefix", "data.csv"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    // Send enough rows to complete many chunks, then give the writers time
+    // to close them while the input is still open.
+    let mut stdin = child.stdin.take().unwrap();
+    writeln!(stdin, "id,value").unwrap();
+    for i in 0..1000 {
+        writeln!(stdin, "{},{}", i, i * i).unwrap();
+    }
+    stdin.flush().unwrap();
+    thread::sleep(Duration::from_millis(500));
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let files = files(&dir, &dir);
+    assert!(!files.is_empty());
+    for file in files {
+        let name = file.file_name().unwrap().to_string_lossy();
+        assert!(name.starts_with('.'), "{} is in place", file.display());
+    }
+}